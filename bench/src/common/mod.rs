@@ -69,7 +69,7 @@ fn benchmark_throughput_func(log_func: fn(usize, usize, f64), flush_func: fn())
     let elapsed = start.elapsed().as_nanos() as f64;
     println!(
         "Throughput is {:.3} million msgs/sec average, total time elapsed: {} ms for {} log messages \n",
-        THROUGHPUT_ITERATION as f64/1_000_000.0 / (elapsed as f64/ 1_000_000_000.0),
+        THROUGHPUT_ITERATION as f64/1_000_000.0 / (elapsed / 1_000_000_000.0),
         elapsed / 1_000_000.0,
         THROUGHPUT_ITERATION
     );
@@ -0,0 +1,142 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Compresses rotated files, see `Logger::compression`. The variants depend on the enabled
+/// features, so matches need a wildcard arm.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Leaves rotated files as they are
+    #[default]
+    None,
+    /// Renames `file` to `file.gz`
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// Renames `file` to `file.zst`
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// Extensions of compressed files
+const COMPRESSED_EXTENSIONS: [&str; 2] = [".gz", ".zst"];
+
+impl Compression {
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => COMPRESSED_EXTENSIONS[0],
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => COMPRESSED_EXTENSIONS[1],
+        }
+    }
+
+    /// Returns `fname` without the extension this compression adds, retention strips it before
+    /// parsing a file name.
+    pub(crate) fn strip_extension(self, fname: &str) -> &str {
+        fname.strip_suffix(self.extension()).unwrap_or(fname)
+    }
+
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+    fn compress(self, reader: &mut BufReader<File>, writer: BufWriter<File>) -> io::Result<()> {
+        match self {
+            Compression::None => Ok(()),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+                io::copy(reader, &mut encoder)?;
+                encoder.finish()?.into_inner()?.sync_all()
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, 0)?;
+                io::copy(reader, &mut encoder)?;
+                encoder.finish()?.into_inner()?.sync_all()
+            }
+        }
+    }
+}
+
+/// Returns `fname` without the extension any `Compression` adds.
+pub(crate) fn strip_compressed_extension(fname: &str) -> &str {
+    COMPRESSED_EXTENSIONS
+        .iter()
+        .find_map(|ext| fname.strip_suffix(ext))
+        .unwrap_or(fname)
+}
+
+/// Writes `path` + extension through a temporary file, which retention never matches, and
+/// removes `path` once the compressed file is complete. Retention may have removed `path`
+/// in the meantime, which is not an error.
+///
+/// Returns the path of the compressed file, `path` itself with `Compression::None`.
+pub(crate) fn compress_file(compression: Compression, path: &Path) -> io::Result<PathBuf> {
+    if compression == Compression::None {
+        return Ok(path.to_path_buf());
+    }
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(compression.extension());
+    let mut tmp = compressed.clone();
+    tmp.push(".tmp");
+
+    let mut reader = match File::open(path) {
+        Ok(f) => BufReader::new(f),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(path.to_path_buf()),
+        Err(e) => return Err(e),
+    };
+    let writer = BufWriter::new(File::create(&tmp)?);
+    if let Err(e) = compression.compress(&mut reader, writer) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, &compressed)?;
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(compressed.into()),
+    }
+}
+
+#[cfg(all(test, any(feature = "gzip", feature = "zstd")))]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_roundtrip() {
+        use std::io::Read;
+
+        let folder = TempDir::new("gzip");
+        let path = folder.join("test.1");
+        fs::write(&path, b"hello\n").unwrap();
+
+        let compressed = compress_file(Compression::Gzip, &path).unwrap();
+
+        assert!(!path.exists());
+        assert_eq!(compressed, folder.join("test.1.gz"));
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(File::open(compressed).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello\n");
+        assert_eq!(strip_compressed_extension("test.1.gz"), "test.1");
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_roundtrip() {
+        let folder = TempDir::new("zstd");
+        let path = folder.join("test.1");
+        fs::write(&path, b"hello\n").unwrap();
+
+        let compressed = compress_file(Compression::Zstd, &path).unwrap();
+
+        assert!(!path.exists());
+        assert_eq!(compressed, folder.join("test.1.zst"));
+        let decoded = zstd::stream::decode_all(File::open(compressed).unwrap()).unwrap();
+        assert_eq!(decoded, b"hello\n");
+        assert_eq!(strip_compressed_extension("test.1.zst"), "test.1");
+    }
+}
@@ -1,3 +1,13 @@
+use std::time::Duration;
+
 pub(crate) const MAX_QUEUE_SIZE: usize = 131_072;
 pub(crate) const BACKGROUND_SLEEP_TIME_STEP_NANOS: u64 = 1_000;
 pub(crate) const MAX_KEEP_FILE: usize = 30;
+pub(crate) const CONTROL_CHECK_INTERVAL: u32 = 64;
+pub(crate) const FINISH_TIMEOUT: Duration = Duration::from_secs(5);
+pub(crate) const BACKEND_LIVENESS_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+pub(crate) const ERROR_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+pub(crate) const MAX_ERROR_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+pub(crate) const FREE_SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+pub(crate) const MMAP_GROW_BYTES: u64 = 64 * 1024 * 1024;
+pub(crate) const CRASH_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
@@ -0,0 +1,219 @@
+use crate::FileNaming;
+use chrono::format::{self, Item, Parsed, StrftimeItems};
+use chrono::prelude::*;
+use std::io;
+
+const INDEX_PLACEHOLDER: &str = "{index}";
+const PID_PLACEHOLDER: &str = "{pid}";
+
+/// A file naming scheme with the placeholders that are constant for the process already
+/// substituted, split around `{index}` if it has one.
+#[derive(Debug)]
+pub(crate) struct FileNameTemplate {
+    before_index: String,
+    after_index: Option<String>,
+    /// Formats around every `{pid}` and `{index}`, each of which matches any number so
+    /// files of earlier processes are found too
+    match_parts: Vec<String>,
+    /// Which of the numbers between `match_parts` is the index
+    index_position: Option<usize>,
+}
+
+/// The date, time and index a file name was rendered with, ordered oldest first.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct FileKey {
+    timestamp: Option<i64>,
+    date: (Option<i32>, Option<u32>, Option<u32>),
+    time: (Option<u32>, Option<u32>, Option<u32>, Option<u32>),
+    index: u64,
+}
+
+impl FileNameTemplate {
+    pub(crate) fn from_naming(naming: &FileNaming, prefix: &str) -> io::Result<Self> {
+        match naming {
+            FileNaming::Timestamp => Self::new("{prefix}.%Y%m%d.%H%M%S", prefix),
+            FileNaming::Index => Self::new("{prefix}.{index}", prefix),
+            FileNaming::DateIndex => Self::new("{prefix}.%Y%m%d.{index}", prefix),
+            FileNaming::Fixed => Self::new("{prefix}", prefix),
+            FileNaming::Template(template) => Self::new(template, prefix),
+        }
+    }
+
+    fn new(template: &str, prefix: &str) -> io::Result<Self> {
+        let invalid = |msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid file name template {}: {}", template, msg),
+            )
+        };
+        if template.contains('/') || template.contains(std::path::MAIN_SEPARATOR) {
+            return Err(invalid("must not contain a path separator"));
+        }
+        if template.matches(INDEX_PLACEHOLDER).count() > 1 {
+            return Err(invalid("{index} may only appear once"));
+        }
+
+        let mut fmt = template.replace("{prefix}", &escape(prefix));
+        if fmt.contains("{hostname}") {
+            let hostname = hostname::get()?;
+            fmt = fmt.replace("{hostname}", &escape(&hostname.to_string_lossy()));
+        }
+        let match_parts: Vec<String> = fmt
+            .split(PID_PLACEHOLDER)
+            .flat_map(|part| part.split(INDEX_PLACEHOLDER))
+            .map(str::to_string)
+            .collect();
+        let index_position = fmt
+            .find(INDEX_PLACEHOLDER)
+            .map(|at| fmt[..at].matches(PID_PLACEHOLDER).count());
+        fmt = fmt.replace(PID_PLACEHOLDER, &std::process::id().to_string());
+
+        let (before_index, after_index) = match fmt.split_once(INDEX_PLACEHOLDER) {
+            Some((before, after)) => (before.to_string(), Some(after.to_string())),
+            None => (fmt, None),
+        };
+        for part in &match_parts {
+            if StrftimeItems::new(part).any(|item| item == Item::Error) {
+                return Err(invalid("bad strftime specifier"));
+            }
+        }
+        Ok(FileNameTemplate {
+            before_index,
+            after_index,
+            match_parts,
+            index_position,
+        })
+    }
+
+    /// Renders the parts before and after `{index}`, the latter is `None` without an index.
+    pub(crate) fn render(&self, now: &DateTime<Local>) -> (String, Option<String>) {
+        (
+            now.format(&self.before_index).to_string(),
+            self.after_index
+                .as_ref()
+                .map(|after| now.format(after).to_string()),
+        )
+    }
+
+    /// Parses `fname` back into what it was rendered from, `None` if this template can not
+    /// have produced it.
+    pub(crate) fn parse(&self, fname: &str) -> Option<FileKey> {
+        let (parsed, index) = self.parse_from(fname, 0, &Parsed::new(), 0)?;
+        let hour = match (parsed.hour_div_12(), parsed.hour_mod_12()) {
+            (Some(div), hour_mod_12) => Some(div * 12 + hour_mod_12.unwrap_or(0)),
+            (None, hour_mod_12) => hour_mod_12,
+        };
+        Some(FileKey {
+            timestamp: parsed.timestamp(),
+            date: (parsed.year(), parsed.month(), parsed.day()),
+            time: (hour, parsed.minute(), parsed.second(), parsed.nanosecond()),
+            index,
+        })
+    }
+
+    /// Matches `s` against `match_parts[part..]`, trying every number in `s` as the one
+    /// following the current part.
+    fn parse_from(
+        &self,
+        s: &str,
+        part: usize,
+        parsed: &Parsed,
+        index: u64,
+    ) -> Option<(Parsed, u64)> {
+        let fmt = &self.match_parts[part];
+        if part + 1 == self.match_parts.len() {
+            let mut parsed = parsed.clone();
+            format::parse(&mut parsed, s, StrftimeItems::new(fmt)).ok()?;
+            return Some((parsed, index));
+        }
+        let bytes = s.as_bytes();
+        (0..bytes.len())
+            .filter(|&i| bytes[i].is_ascii_digit())
+            .find_map(|i| {
+                let mut parsed = parsed.clone();
+                format::parse(&mut parsed, &s[..i], StrftimeItems::new(fmt)).ok()?;
+                let digits = bytes[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+                (1..=digits).find_map(|n| {
+                    let index = match self.index_position {
+                        Some(position) if position == part => s[i..i + n].parse().ok()?,
+                        _ => index,
+                    };
+                    self.parse_from(&s[i + n..], part + 1, &parsed, index)
+                })
+            })
+    }
+}
+
+/// Escapes a substituted value so strftime keeps it literal.
+fn escape(s: &str) -> String {
+    s.replace('%', "%%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(template: &str, prefix: &str) -> FileNameTemplate {
+        FileNameTemplate::from_naming(&FileNaming::Template(template.into()), prefix).unwrap()
+    }
+
+    #[test]
+    fn test_render_and_parse() {
+        let template = template("{prefix}-{pid}-%Y%m%dT%H%M%S.log", "svc%1");
+        let now = Local.with_ymd_and_hms(2024, 3, 9, 7, 5, 1).unwrap();
+        let (name, index) = template.render(&now);
+        assert_eq!(
+            name,
+            format!("svc%1-{}-20240309T070501.log", std::process::id())
+        );
+        assert!(index.is_none());
+        assert!(template.parse(&name).is_some());
+        assert!(template.parse("svc%1-1-20240309T070501.log").is_some());
+        assert!(template.parse("svc%1").is_none());
+        assert!(template.parse("svc%1--20240309T070501.log").is_none());
+        assert!(template.parse(&format!("{}.gz", name)).is_none());
+        assert!(template.parse("svc%1-1-20240309T070501.log.tar").is_none());
+        assert!(
+            template.parse("svc%1-9-20240309T070501.log")
+                < template.parse("svc%1-1-20240309T070502.log")
+        );
+    }
+
+    #[test]
+    fn test_index() {
+        let template = template("{prefix}.%Y%m%d.{index}.log", "app");
+        let now = Local.with_ymd_and_hms(2024, 3, 9, 7, 5, 1).unwrap();
+        assert_eq!(
+            template.render(&now),
+            ("app.20240309.".to_string(), Some(".log".to_string()))
+        );
+        assert!(template.parse("app.20240309..log").is_none());
+        assert!(template.parse("app.20240309.x.log").is_none());
+        let key = |name| template.parse(name).unwrap();
+        assert!(key("app.20240309.9.log") < key("app.20240309.10.log"));
+        assert!(key("app.20240309.10.log") < key("app.20240310.1.log"));
+    }
+
+    #[test]
+    fn test_builtin_naming() {
+        let timestamp = FileNameTemplate::from_naming(&FileNaming::Timestamp, "log").unwrap();
+        assert!(timestamp.parse("log.20240309.070501").is_some());
+        assert!(timestamp.parse("log").is_none());
+        assert!(timestamp.parse("log.other").is_none());
+        assert!(timestamp.parse("log_archive.tar").is_none());
+        assert!(timestamp.parse("log.20240309.070501.gz").is_none());
+
+        let index = FileNameTemplate::from_naming(&FileNaming::Index, "log").unwrap();
+        assert!(index.parse("log.2").unwrap() < index.parse("log.10").unwrap());
+        assert!(index.parse("log.20240309.070501").is_none());
+        assert!(index.parse("log.1x").is_none());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let new = |t: &str| FileNameTemplate::from_naming(&FileNaming::Template(t.into()), "app");
+        assert!(new("logs/{prefix}").is_err());
+        assert!(new("{index}{index}").is_err());
+        assert!(new("{prefix}.%Q").is_err());
+    }
+}
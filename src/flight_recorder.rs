@@ -0,0 +1,244 @@
+//! Keeps the most recent records in a ring buffer inside a shared file mapping, e.g. under
+//! `/dev/shm`. The page cache holds them as soon as they are copied, so they survive a crash or
+//! SIGKILL of the process, and `read_flight_recorder` extracts them post-mortem. Only records
+//! the backend took from the queue and formatted are copied, those still queued are lost.
+//!
+//! The file starts with a header of `HEADER_LEN` bytes: the magic, the capacity of the ring
+//! and the number of bytes ever written to it, both as native endian `u64`. The ring follows.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"LLLFLREC";
+const HEADER_LEN: usize = 64;
+
+/// Writes records into the mapped ring buffer of a flight recorder file
+#[cfg(unix)]
+pub(crate) struct FlightRecorder {
+    ptr: *mut u8,
+    capacity: usize,
+    head: u64,
+}
+
+// SAFETY: the mapping is owned by the recorder and only written through `&mut self`
+#[cfg(unix)]
+unsafe impl Send for FlightRecorder {}
+
+#[cfg(unix)]
+impl FlightRecorder {
+    /// Maps `path` with a ring of `capacity` bytes. Records of an earlier run in a file of the
+    /// same capacity are kept until they are overwritten.
+    pub(crate) fn open(path: &Path, capacity: u64) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        if capacity == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the flight recorder needs a capacity",
+            ));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let head = match read_header(&file) {
+            Ok((c, head)) if c == capacity => head,
+            _ => {
+                file.set_len(0)?;
+                0
+            }
+        };
+        let len = capacity + HEADER_LEN as u64;
+        // a full file system is reported here rather than as a SIGBUS on the mapping
+        crate::mmap_writer::preallocate(&file, len)?;
+        let len =
+            usize::try_from(len).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: a fresh shared mapping of a file that is `len` bytes long
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let recorder = FlightRecorder {
+            ptr: ptr as *mut u8,
+            capacity: len - HEADER_LEN,
+            head,
+        };
+        let mut header = [0u8; 16];
+        header[..8].copy_from_slice(MAGIC);
+        header[8..].copy_from_slice(&capacity.to_ne_bytes());
+        // SAFETY: the header is within the mapping
+        unsafe { std::ptr::copy_nonoverlapping(header.as_ptr(), recorder.ptr, header.len()) };
+        recorder.store_head();
+        Ok(recorder)
+    }
+
+    /// Copies `buf` into the ring, overwriting the oldest bytes, and publishes the new head.
+    pub(crate) fn write(&mut self, mut buf: &[u8]) {
+        self.head += buf.len() as u64;
+        if buf.len() > self.capacity {
+            buf = &buf[buf.len() - self.capacity..];
+        }
+        let pos = ((self.head - buf.len() as u64) % self.capacity as u64) as usize;
+        let first = buf.len().min(self.capacity - pos);
+        // SAFETY: both copies stay within the ring that follows the header
+        unsafe {
+            let ring = self.ptr.add(HEADER_LEN);
+            std::ptr::copy_nonoverlapping(buf.as_ptr(), ring.add(pos), first);
+            std::ptr::copy_nonoverlapping(buf[first..].as_ptr(), ring, buf.len() - first);
+        }
+        self.store_head();
+    }
+
+    /// Stores the head after the bytes it covers, a reader never sees a head past the data.
+    fn store_head(&self) {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        // SAFETY: the mapping is page aligned, so is the head at offset 16
+        let head = unsafe { AtomicU64::from_ptr(self.ptr.add(16) as *mut u64) };
+        head.store(self.head, Ordering::Release);
+    }
+}
+
+#[cfg(unix)]
+impl Drop for FlightRecorder {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and the length describe the mapping created by `open`
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.capacity + HEADER_LEN) };
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) struct FlightRecorder;
+
+#[cfg(not(unix))]
+impl FlightRecorder {
+    pub(crate) fn open(_path: &Path, _capacity: u64) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the flight recorder is only available on unix",
+        ))
+    }
+
+    pub(crate) fn write(&mut self, _buf: &[u8]) {}
+}
+
+/// Capacity and head of a flight recorder file
+fn read_header(file: &File) -> io::Result<(u64, u64)> {
+    let mut header = [0u8; 24];
+    read_exact_at(file, &mut header, 0)?;
+    if &header[..8] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a flight recorder file",
+        ));
+    }
+    let capacity = u64::from_ne_bytes(header[8..16].try_into().unwrap());
+    let head = u64::from_ne_bytes(header[16..].try_into().unwrap());
+    Ok((capacity, head))
+}
+
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+/// Reads the last `n` records of the flight recorder file at `path`, oldest first, e.g. after
+/// the process that wrote it crashed. Once the ring wrapped around, the oldest record is left
+/// out, it may have been cut by newer ones.
+pub fn read_flight_recorder(path: impl AsRef<Path>, n: usize) -> io::Result<Vec<String>> {
+    let file = File::open(path)?;
+    let (capacity, head) = read_header(&file)?;
+    // a cut or corrupt file must not make the ring reach past its end
+    let file_len = file.metadata()?.len();
+    if capacity == 0 || file_len.saturating_sub(HEADER_LEN as u64) < capacity {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the flight recorder file is shorter than its capacity",
+        ));
+    }
+    let len = head.min(capacity);
+    let mut data = vec![0u8; len as usize];
+    let pos = (head - len) % capacity;
+    let first = (capacity - pos).min(len) as usize;
+    read_exact_at(&file, &mut data[..first], HEADER_LEN as u64 + pos)?;
+    read_exact_at(&file, &mut data[first..], HEADER_LEN as u64)?;
+
+    // the oldest record is cut once the ring wrapped around
+    let start = match head > capacity {
+        true => data
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(data.len(), |i| i + 1),
+        false => 0,
+    };
+    let mut records: Vec<String> = data[start..]
+        .split(|b| *b == b'\n')
+        .filter(|r| !r.is_empty())
+        .map(|r| String::from_utf8_lossy(r).into_owned())
+        .collect();
+    records.drain(..records.len().saturating_sub(n));
+    Ok(records)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_flight_recorder() {
+        let folder = TempDir::new("flight");
+        let path = folder.join("recorder");
+
+        let mut recorder = FlightRecorder::open(&path, 16).unwrap();
+        recorder.write(b"one\n");
+        recorder.write(b"two\n");
+        assert_eq!(read_flight_recorder(&path, 10).unwrap(), ["one", "two"]);
+        assert_eq!(read_flight_recorder(&path, 1).unwrap(), ["two"]);
+
+        // wraps around and overwrites "one"
+        recorder.write(b"three\n");
+        recorder.write(b"four\n");
+        assert_eq!(
+            read_flight_recorder(&path, 10).unwrap(),
+            ["two", "three", "four"]
+        );
+        drop(recorder);
+
+        // a new run continues after the records of the last one, the oldest record is left
+        // out as it may be cut
+        let mut recorder = FlightRecorder::open(&path, 16).unwrap();
+        recorder.write(b"five\n");
+        assert_eq!(read_flight_recorder(&path, 10).unwrap(), ["four", "five"]);
+        drop(recorder);
+
+        assert!(read_flight_recorder(path.with_extension("missing"), 10).is_err());
+    }
+
+    #[test]
+    fn test_flight_recorder_rejects_a_cut_file() {
+        let folder = TempDir::new("flight_cut");
+        let path = folder.join("recorder");
+
+        let mut recorder = FlightRecorder::open(&path, 4096).unwrap();
+        recorder.write(b"one\n");
+        drop(recorder);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(HEADER_LEN as u64 + 100).unwrap();
+        let err = read_flight_recorder(&path, 10).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
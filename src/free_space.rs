@@ -0,0 +1,52 @@
+use std::io;
+use std::path::Path;
+
+/// What a logger does while the file system of its folder has less free space than
+/// `Logger::min_free_space` asks for
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LowSpaceAction {
+    /// Deletes the oldest files of the logger, regardless of the retention limits, until
+    /// enough space is free. The file being written is always kept.
+    DeleteOldest,
+    /// Drops records below `LogLevel::Warn`, counting them in `LoggerStats::dropped_records`,
+    /// until enough space is free again
+    WarningsOnly,
+}
+
+/// Bytes an unprivileged process can still write to the file system holding `path`.
+#[cfg(unix)]
+pub(crate) fn available_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: `path` is a valid C string and `stat` is only read after statvfs filled it in
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        #[allow(clippy::unnecessary_cast)]
+        Ok((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space is only checked on unix",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_available_space() {
+        assert!(available_space(&std::env::temp_dir()).unwrap() > 0);
+        assert!(available_space(Path::new("/nonexistent/lll")).is_err());
+    }
+}
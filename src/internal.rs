@@ -1,5 +1,43 @@
+use std::fmt;
+use std::io;
+
+use crate::LogWriter;
 pub use crate::LoggingFunc;
 
 pub fn log(func: LoggingFunc) {
-    crate::logger().log(func)
+    crate::GLOBAL_LOGGER.with(|logger| match logger {
+        Some(logger) => logger.log(func),
+        None => crate::log_uninit(func),
+    })
+}
+
+/// Formats `args` straight into the backend's writer, skipping the intermediate `String`.
+pub fn write_fmt(w: &mut LogWriter, args: fmt::Arguments) -> io::Result<()> {
+    if let Some(s) = args.as_str() {
+        return w.write_str(s);
+    }
+    let mut adapter = FmtAdapter {
+        inner: w,
+        error: None,
+    };
+    match fmt::write(&mut adapter, args) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(adapter
+            .error
+            .unwrap_or_else(|| io::Error::other("formatter error"))),
+    }
+}
+
+struct FmtAdapter<'a, 'b> {
+    inner: &'a mut LogWriter<'b>,
+    error: Option<io::Error>,
+}
+
+impl fmt::Write for FmtAdapter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_str(s).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
 }
@@ -2,34 +2,204 @@ extern crate core;
 
 use chrono::prelude::*;
 use core_affinity::CoreId;
-use once_cell::sync::OnceCell;
+use file_name::FileNameTemplate;
+use flight_recorder::FlightRecorder;
+use rotation::{Opened, PendingFile, RotateHook, RotationHelper, RotationThreads};
 use std::borrow::Cow;
 use std::fs::{self, File};
-use std::io::{self, BufWriter, Write};
-use std::path::Path;
-use std::sync::atomic::AtomicU8;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use ufmt::{uwrite, uwriteln};
-
-use symlink::{remove_symlink_auto, symlink_auto};
+use ufmt::{uWrite, uwrite};
+use writer::LogFileWriter;
 
 pub mod internal;
 pub mod log_proxy;
 pub mod macros;
 
+mod compress;
 mod consts;
+mod file_name;
+mod flight_recorder;
 mod fmt_utils;
+mod free_space;
+#[cfg(unix)]
+mod mmap_writer;
+mod rotation;
+mod signal;
+#[cfg(test)]
+mod test_util;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring_writer;
+mod writer;
+
+// re-exported so that the `u*!` macros can be used without a direct `ufmt` dependency
+pub use compress::Compression;
+pub use flight_recorder::read_flight_recorder;
+pub use free_space::LowSpaceAction;
+pub use ufmt;
+pub use writer::WriterKind;
+
+/// The logger used by the macros, replaced when `Logger::init` runs again after `finish`
+pub static GLOBAL_LOGGER: GlobalLogger = GlobalLogger::new();
+/// Set once the global logger is finished, cleared when `Logger::init` installs a new one
+pub static GLOBAL_LOGGER_STOP_FLAG: std::sync::Mutex<bool> = std::sync::Mutex::new(false);
+
+/// Holds the global logger, see `GLOBAL_LOGGER`.
+///
+/// Every thread caches the logger it logs to, so logging does not touch a shared reference
+/// count. A replaced logger is freed once each thread that logged to it logged again or exited.
+#[derive(Debug)]
+pub struct GlobalLogger {
+    current: std::sync::Mutex<Option<Arc<LoggerHandle>>>,
+    /// Bumped whenever the logger is replaced, so the thread caches know to reload it
+    generation: AtomicU64,
+}
+
+thread_local! {
+    static CACHED_LOGGER: std::cell::RefCell<(u64, Option<Arc<LoggerHandle>>)> =
+        const { std::cell::RefCell::new((0, None)) };
+}
+
+impl GlobalLogger {
+    const fn new() -> Self {
+        GlobalLogger {
+            current: std::sync::Mutex::new(None),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// The global logger, if `Logger::init` was called
+    pub fn get(&self) -> Option<Arc<LoggerHandle>> {
+        self.current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Installs `logger`, returning the one it replaces.
+    fn set(&self, logger: Arc<LoggerHandle>) -> Option<Arc<LoggerHandle>> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = current.replace(logger);
+        self.generation
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        previous
+    }
+
+    fn is_current(&self, logger: &LoggerHandle) -> bool {
+        self.current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_deref()
+            .is_some_and(|current| std::ptr::eq(current, logger))
+    }
 
-pub static GLOBAL_LOGGER: OnceCell<Logger> = OnceCell::new();
-pub static GLOBAL_LOGGER_STOP_FLAG: once_cell::sync::Lazy<std::sync::Mutex<bool>> =
-    once_cell::sync::Lazy::new(|| std::sync::Mutex::new(false));
+    /// Calls `f` with the global logger of this thread's cache, reloading it if it was replaced.
+    pub(crate) fn with<R>(&self, f: impl FnOnce(Option<&LoggerHandle>) -> R) -> R {
+        let generation = self.generation.load(std::sync::atomic::Ordering::Acquire);
+        let mut f = Some(f);
+        let res = CACHED_LOGGER.try_with(|cache| {
+            let replaced = match cache.try_borrow_mut() {
+                Ok(mut cache) if cache.0 != generation => {
+                    Some(std::mem::replace(&mut *cache, (generation, self.get())))
+                }
+                _ => None,
+            };
+            // dropping the last reference finishes the replaced logger, which may log
+            drop(replaced);
+            let cache = cache.try_borrow().ok()?;
+            match cache.0 == generation {
+                true => f.take().map(|f| f(cache.1.as_deref())),
+                false => None,
+            }
+        });
+        match (res, f) {
+            (Ok(Some(res)), _) => res,
+            // the cache is gone with the thread, or in use by a record logged while reloading it
+            (_, Some(f)) => f(self.get().as_deref()),
+            (_, None) => unreachable!(),
+        }
+    }
+}
 
 const TIME_FORMAT_STR: &str = "%H:%M:%S";
 
+/// What happens to records logged while no logger is running, i.e. before `Logger::init` or
+/// after `Logger::finish`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum UninitPolicy {
+    /// Drops them, counting them in `uninit_dropped_count`
+    #[default]
+    Drop,
+    /// Keeps up to the given number of them and replays them once the logger is initialized,
+    /// further records are dropped and counted
+    Buffer(usize),
+    /// Writes them to stderr
+    Stderr,
+}
+
+struct UninitState {
+    policy: UninitPolicy,
+    buffered: Vec<LoggingFunc>,
+}
+
+static UNINIT_STATE: std::sync::Mutex<UninitState> = std::sync::Mutex::new(UninitState {
+    policy: UninitPolicy::Drop,
+    buffered: Vec::new(),
+});
+static UNINIT_DROPPED_COUNT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Selects what happens to records logged while no logger is running, defaults to
+/// `UninitPolicy::Drop`. Call it before the first log statement that may run early.
+pub fn set_uninit_policy(policy: UninitPolicy) {
+    let mut state = UNINIT_STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state.policy = policy;
+    if let UninitPolicy::Buffer(n) = policy {
+        if state.buffered.len() > n {
+            let dropped = state.buffered.len() - n;
+            state.buffered.truncate(n);
+            UNINIT_DROPPED_COUNT.fetch_add(dropped as u64, std::sync::atomic::Ordering::Relaxed);
+        }
+    } else {
+        state.buffered.clear();
+    }
+}
+
+/// Number of records dropped because no logger was running
+pub fn uninit_dropped_count() -> u64 {
+    UNINIT_DROPPED_COUNT.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+fn log_uninit(func: LoggingFunc) {
+    let state = UNINIT_STATE.lock().unwrap_or_else(|e| e.into_inner());
+    // the logger may have been initialized while we waited for the lock
+    if let Some(logger) = GLOBAL_LOGGER.get() {
+        drop(state);
+        return logger.log(func);
+    }
+    apply_uninit_policy(state, func);
+}
+
+fn apply_uninit_policy(mut state: std::sync::MutexGuard<UninitState>, func: LoggingFunc) {
+    match state.policy {
+        UninitPolicy::Buffer(n) if state.buffered.len() < n => state.buffered.push(func),
+        UninitPolicy::Drop | UninitPolicy::Buffer(_) => {
+            UNINIT_DROPPED_COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        UninitPolicy::Stderr => {
+            drop(state);
+            func.eprint();
+        }
+    }
+}
+
 thread_local! {
     pub static TID: std::cell::Cell<&'static str> = std::cell::Cell::new(Box::leak(format!("{}", gettid::gettid()).into_boxed_str()));
+    /// Set on backend threads, a panic there can not wait for itself to be written
+    static IS_BACKEND: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
 }
 
 pub struct UString(pub String);
@@ -50,7 +220,7 @@ impl ufmt::uDisplay for UString {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LogLevel {
     Trace = 0,
     Debug = 1,
@@ -110,15 +280,21 @@ impl LogLevel {
     }
 }
 
+/// The writer a logging closure formats its message into on the backend thread.
+pub type LogWriter<'a> = dyn uWrite<Error = io::Error> + 'a;
+
+type FormatFn = Box<dyn Fn(&mut LogWriter) -> io::Result<()> + Send>;
+
 /*
  NOTE: this struct should be as small as possible to avoid cache miss
 */
 pub struct LoggingFunc {
-    func: Box<dyn Fn() -> Cow<'static, str> + Send>,
+    func: FormatFn,
     file: &'static str,
     line: u32,
     tid: &'static str,
     level: LogLevel,
+    logger_id: u8, /* 0 -> main file, otherwise index of the named file + 1 */
     system_time: u64,
 }
 
@@ -134,6 +310,29 @@ impl LoggingFunc {
     ) -> LoggingFunc
     where
         T: Fn() -> Cow<'static, str> + 'static + Send,
+    {
+        Self::with_writer(
+            move |w: &mut LogWriter| w.write_str(func().as_ref()),
+            file,
+            line,
+            tid,
+            lvl,
+            system_time,
+        )
+    }
+
+    /// Like [`LoggingFunc::new`], but the closure writes the message straight into the
+    /// backend's buffer instead of returning an owned string.
+    pub fn with_writer<T>(
+        func: T,
+        file: &'static str,
+        line: u32,
+        tid: &'static str,
+        lvl: LogLevel,
+        system_time: u64,
+    ) -> LoggingFunc
+    where
+        T: Fn(&mut LogWriter) -> io::Result<()> + 'static + Send,
     {
         LoggingFunc {
             func: Box::new(func),
@@ -141,24 +340,61 @@ impl LoggingFunc {
             line,
             tid,
             level: lvl,
+            logger_id: 0,
             system_time,
         }
     }
-    fn invoke(self, rolling_logger: &mut RollingLogger) {
+
+    fn invoke(&self, rolling_logger: &mut RollingLogger) {
         rolling_logger.write_date_time_str(self.system_time);
-        let output = (self.func)();
-        let output_str = output.as_ref();
 
-        let _ = uwriteln!(
+        let _ = uwrite!(
             rolling_logger,
+            "[{}] {}:{} {} ",
+            self.tid,
+            self.file,
+            self.line,
+            self.level.to_str()
+        );
+        self.format_into(rolling_logger);
+        let _ = rolling_logger.write_char('\n');
+    }
+
+    /// Writes the record to stderr, used when no logger is running.
+    fn eprint(&self) {
+        eprintln!(
             "[{}] {}:{} {} {}",
             self.tid,
             self.file,
             self.line,
             self.level.to_str(),
-            output_str
+            self.format_message()
         );
     }
+
+    /// Formats the message into an owned string, used when the record cannot reach the backend.
+    fn format_message(&self) -> String {
+        let mut output = UString(String::new());
+        self.format_into(&mut output);
+        output.0
+    }
+
+    /// Runs the user's formatting closure, turning a panic from a buggy `Display`/`Debug` impl
+    /// into a placeholder so it can not take down the backend thread.
+    fn format_into(&self, w: &mut LogWriter) {
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| (self.func)(&mut *w)));
+        if let Err(payload) = res {
+            let _ = uwrite!(w, "<formatting panicked: {}>", panic_message(&*payload));
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
 }
 
 /// Determines how often a file should be rolled over
@@ -254,13 +490,304 @@ impl RollingCondition {
     }
 }
 
+/// Determines when buffered records are written to the file and synced to disk
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq)]
+pub struct FlushPolicy {
+    idle: bool,
+    interval_opt: Option<Duration>,
+    max_bytes_opt: Option<u64>,
+    level_opt: Option<LogLevel>,
+    sync_interval_opt: Option<Duration>,
+}
+
+impl FlushPolicy {
+    /// Constructs a new policy that does not yet have any condition set, so records are
+    /// only written when the buffer is full, on `Logger::flush` and on shutdown.
+    pub fn new() -> FlushPolicy {
+        FlushPolicy::default()
+    }
+
+    /// Flushes whenever the queue runs empty
+    pub fn idle(mut self) -> FlushPolicy {
+        self.idle = true;
+        self
+    }
+
+    /// Flushes when the given time has passed since the last flush
+    pub fn interval(mut self, x: Duration) -> FlushPolicy {
+        self.interval_opt = Some(x);
+        self
+    }
+
+    /// Flushes once this many bytes are buffered
+    pub fn max_bytes(mut self, x: u64) -> FlushPolicy {
+        self.max_bytes_opt = Some(x);
+        self
+    }
+
+    /// Flushes immediately after a record at or above the given level, e.g. `LogLevel::Warn`
+    pub fn level(mut self, x: LogLevel) -> FlushPolicy {
+        self.level_opt = Some(x);
+        self
+    }
+
+    /// Calls `fdatasync` on the file when the given time has passed since the last sync,
+    /// and when the file is closed
+    pub fn sync_interval(mut self, x: Duration) -> FlushPolicy {
+        self.sync_interval_opt = Some(x);
+        self
+    }
+}
+
+/// What the backend does while a file can not be written, e.g. because the disk is full.
+///
+/// Every failed write, flush or open counts in `LoggerStats::write_errors`. The file is tried
+/// again after a backoff, records logged meanwhile go to the `Fallback`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ErrorPolicy {
+    fallback: Fallback,
+    retry_backoff: Duration,
+    max_retry_backoff: Duration,
+}
+
+/// Where records go while their file can not be written, see `ErrorPolicy`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum Fallback {
+    /// Drops them, counting them in `LoggerStats::dropped_records`
+    #[default]
+    Drop,
+    /// Writes them to stderr
+    Stderr,
+    /// Writes them to a file of the same name in the given folder, which is rolled and
+    /// retained like the original one
+    Folder(String),
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy::new()
+    }
+}
+
+impl ErrorPolicy {
+    /// Drops records while their file fails, trying it again after
+    /// `consts::ERROR_RETRY_BACKOFF`, doubled after every failed attempt up to
+    /// `consts::MAX_ERROR_RETRY_BACKOFF`.
+    pub fn new() -> ErrorPolicy {
+        ErrorPolicy {
+            fallback: Fallback::Drop,
+            retry_backoff: consts::ERROR_RETRY_BACKOFF,
+            max_retry_backoff: consts::MAX_ERROR_RETRY_BACKOFF,
+        }
+    }
+
+    /// Sends records to `fallback` while their file fails
+    pub fn fallback(mut self, fallback: Fallback) -> ErrorPolicy {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Waits `initial` before trying a failed file again, doubling the wait after every failed
+    /// attempt up to `max`
+    pub fn retry_backoff(mut self, initial: Duration, max: Duration) -> ErrorPolicy {
+        self.retry_backoff = initial;
+        self.max_retry_backoff = max.max(initial);
+        self
+    }
+}
+
+/// Counters of a logger, see `LoggerHandle::stats`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LoggerStats {
+    /// Failed writes, flushes and opens of a file
+    pub write_errors: u64,
+    /// Records lost because their file could not be written, or was short of space with
+    /// `LowSpaceAction::WarningsOnly`
+    pub dropped_records: u64,
+    /// Records written to the `Fallback` instead of their file
+    pub fallback_records: u64,
+}
+
+/// The counters behind `LoggerStats`, shared by the backend and the handle
+#[derive(Debug, Default)]
+struct Stats {
+    write_errors: std::sync::atomic::AtomicU64,
+    dropped_records: std::sync::atomic::AtomicU64,
+    fallback_records: std::sync::atomic::AtomicU64,
+}
+
+impl Stats {
+    fn snapshot(&self) -> LoggerStats {
+        use std::sync::atomic::Ordering::Relaxed;
+        LoggerStats {
+            write_errors: self.write_errors.load(Relaxed),
+            dropped_records: self.dropped_records.load(Relaxed),
+            fallback_records: self.fallback_records.load(Relaxed),
+        }
+    }
+}
+
+/// Determines how rolled files are named.
+///
+/// Retention only counts and deletes files whose whole name parses as the chosen scheme,
+/// oldest first by the date, time and index in the name.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum FileNaming {
+    /// `prefix.YYYYMMDD.HHMMSS`, two rollovers within the same second share a file
+    #[default]
+    Timestamp,
+    /// `prefix.1`, `prefix.2`, ..., every rollover gets the next index
+    Index,
+    /// `prefix.YYYYMMDD.1`, `prefix.YYYYMMDD.2`, ..., the index starts over every day
+    DateIndex,
+    /// `prefix` itself, for tools like logrotate that move the file away and then ask the
+    /// logger to reopen it, see `Logger::reopen_on_sighup`. Rollovers keep writing to the file.
+    /// A file deleted without asking for a reopen is only noticed by the first record of the
+    /// next second, records flushed to it before are lost with it.
+    Fixed,
+    /// A user-defined name such as `{prefix}-{hostname}-{pid}-%Y%m%dT%H%M%S.log`.
+    ///
+    /// `{prefix}`, `{hostname}` and `{pid}` are substituted once, `%` items are strftime
+    /// specifiers rendered at rollover and an optional `{index}` works like `DateIndex`.
+    Template(String),
+}
+
+/// An additional file written by the same backend thread, see `Logger::named_file`
+#[derive(Clone, Debug)]
+pub struct NamedFile {
+    rc: RollingCondition,
+    folder: String,
+    prefix: String,
+    max_files: usize,
+    max_total_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    file_naming: FileNaming,
+    compression: Compression,
+    on_rotate: Option<RotateHook>,
+    min_free_space: Option<(u64, LowSpaceAction)>,
+    writer_kind: WriterKind,
+    flight_recorder: Option<(String, u64)>,
+}
+
+impl NamedFile {
+    pub fn new(rc: RollingCondition, folder: String, prefix: String) -> Self {
+        NamedFile {
+            rc,
+            folder,
+            prefix,
+            max_files: consts::MAX_KEEP_FILE,
+            max_total_bytes: None,
+            max_age: None,
+            file_naming: FileNaming::default(),
+            compression: Compression::None,
+            on_rotate: None,
+            min_free_space: None,
+            writer_kind: WriterKind::default(),
+            flight_recorder: None,
+        }
+    }
+
+    /// Deletes the oldest files once there are more than the given number, counting the file
+    /// being written
+    pub fn max_files(mut self, max_files: usize) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    /// Deletes the oldest files once all files together take up more than the given size,
+    /// the file being written is always kept
+    pub fn max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+        self.max_total_bytes = Some(max_total_bytes);
+        self
+    }
+
+    /// Deletes files last modified longer ago than the given duration
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets how rolled files are named, defaults to `FileNaming::Timestamp`
+    pub fn file_naming(mut self, file_naming: FileNaming) -> Self {
+        self.file_naming = file_naming;
+        self
+    }
+
+    /// Compresses every file after it is rolled over, on a low priority thread of its own,
+    /// defaults to `Compression::None`
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Calls `f` with the closed and the new file after every rollover of this file, once
+    /// the closed file has been compressed
+    pub fn on_rotate(mut self, f: impl Fn(&Path, &Path) + Send + Sync + 'static) -> Self {
+        self.on_rotate = Some(RotateHook::new(f));
+        self
+    }
+
+    /// Takes `action` while the folder of this file has less than `min_free_bytes` available
+    pub fn min_free_space(mut self, min_free_bytes: u64, action: LowSpaceAction) -> Self {
+        self.min_free_space = Some((min_free_bytes, action));
+        self
+    }
+
+    /// Selects how records are written to this file, defaults to `WriterKind::Buffered`
+    pub fn writer(mut self, writer_kind: WriterKind) -> Self {
+        self.writer_kind = writer_kind;
+        self
+    }
+
+    /// Copies every record of this file into a ring buffer of `capacity` bytes mapped from the
+    /// file at `path`, see `Logger::flight_recorder`
+    pub fn flight_recorder(mut self, path: String, capacity: u64) -> Self {
+        self.flight_recorder = Some((path, capacity));
+        self
+    }
+
+    /// Bytes the mmap writer maps up front
+    fn preallocate(&self) -> u64 {
+        self.rc.max_size_opt.unwrap_or(consts::MMAP_GROW_BYTES)
+    }
+}
+
 pub struct RollingLogger {
     condition: RollingCondition,
-    prefix: String,
+    flush_policy: FlushPolicy,
     folder: String,
-    max_files: usize,
-    writer_buffer: Option<BufWriter<File>>,
+    template: Arc<FileNameTemplate>,
+    rotation_helper: RotationHelper,
+    last_index: Option<(String /* file name without index */, u64)>,
+    writer_kind: WriterKind,
+    /// Bytes the mmap writer maps up front
+    preallocate: u64,
+    writer_buffer: Option<LogFileWriter>,
+    /// Collects a record for the mmap writer while `staging`, see `write_record`
+    record_buf: Vec<u8>,
+    staging: bool,
+    /// Ring buffer every formatted record is copied to, see `Logger::flight_recorder`
+    recorder: Option<FlightRecorder>,
+    /// The file closed by `rollover`, handed to the rotation helper once the next one is open
+    closed_file: Option<(File, bool /* need_sync */)>,
+    current_file_path: Option<PathBuf>,
     current_file_size: u64,
+    flushed_file_size: u64,
+    last_flush_ns: u64,
+    last_sync_ns: u64,
+    need_sync: bool,
+    error_policy: ErrorPolicy,
+    min_free_space: Option<(u64, LowSpaceAction)>,
+    /// Whether the last check found less free space than `min_free_space`
+    low_space: bool,
+    next_space_check_ns: u64,
+    /// Set while the file can not be written
+    failure: Option<Failure>,
+    /// The file records go to while this one fails, with `Fallback::Folder`
+    fallback: Option<Box<RollingLogger>>,
+    /// What `fallback` is opened from
+    fallback_file: Option<NamedFile>,
+    stats: Arc<Stats>,
     time_fmt_str: String,
     cached_date_time: (
         u64,    /* unix_timestamp_sec */
@@ -268,6 +795,12 @@ pub struct RollingLogger {
     ),
 }
 
+/// A file that failed, and when to try it again
+struct Failure {
+    retry_at_ns: u64,
+    backoff: Duration,
+}
+
 impl RollingLogger {
     pub fn new(
         rc: RollingCondition,
@@ -276,94 +809,224 @@ impl RollingLogger {
         prefix: String,
         max_files: usize,
     ) -> Self {
-        if std::fs::metadata(&folder).is_err() {
-            std::fs::create_dir_all(&folder).expect("Failed to create log folder");
+        let file = NamedFile::new(rc, folder, prefix).max_files(max_files);
+        let rotation = RotationThreads::start().expect("Failed to start rotation threads");
+        Self::build(
+            file,
+            time_fmt_str,
+            FlushPolicy::new().idle(),
+            ErrorPolicy::new(),
+            Arc::default(),
+            &rotation,
+        )
+        .expect("Failed to open log file")
+    }
+
+    fn build(
+        file: NamedFile,
+        time_fmt_str: String,
+        flush_policy: FlushPolicy,
+        error_policy: ErrorPolicy,
+        stats: Arc<Stats>,
+        rotation: &RotationThreads,
+    ) -> io::Result<Self> {
+        if std::fs::metadata(&file.folder).is_err() {
+            std::fs::create_dir_all(&file.folder)?;
         }
+        let fallback_file = match &error_policy.fallback {
+            Fallback::Folder(folder) => Some(NamedFile {
+                folder: folder.clone(),
+                flight_recorder: None,
+                ..file.clone()
+            }),
+            _ => None,
+        };
 
+        let template = Arc::new(FileNameTemplate::from_naming(
+            &file.file_naming,
+            &file.prefix,
+        )?);
+        let rotation_helper = rotation.helper(&file, template.clone());
+        let preallocate = file.preallocate();
+        let recorder = match &file.flight_recorder {
+            Some((path, capacity)) => Some(FlightRecorder::open(Path::new(path), *capacity)?),
+            None => None,
+        };
         let mut rolling_logger = RollingLogger {
-            condition: rc,
-            prefix,
-            folder,
-            max_files,
+            condition: file.rc,
+            flush_policy,
+            folder: file.folder,
+            template,
+            rotation_helper,
+            last_index: None,
             time_fmt_str,
+            writer_kind: file.writer_kind,
+            preallocate,
             writer_buffer: None,
+            record_buf: Vec::new(),
+            staging: false,
+            recorder,
+            closed_file: None,
+            current_file_path: None,
             current_file_size: 0,
+            flushed_file_size: 0,
+            last_flush_ns: 0,
+            last_sync_ns: 0,
+            need_sync: false,
+            error_policy,
+            min_free_space: file.min_free_space,
+            low_space: false,
+            next_space_check_ns: 0,
+            failure: None,
+            fallback: None,
+            fallback_file,
+            stats,
             cached_date_time: (0, "".into()),
         };
-        rolling_logger
-            .open_writer_if_needed(&Local::now())
-            .expect("Failed to open log file");
-        rolling_logger
+        rolling_logger.open_writer_if_needed(&Local::now())?;
+        // the symlink and retention are in place before the first record
+        rolling_logger.rotation_helper.wait_idle();
+        Ok(rolling_logger)
     }
 }
 
+/// Stops the logger it was returned for when dropped.
 #[derive(Debug)]
-pub struct LoggerGuard;
+pub struct LoggerGuard {
+    logger: Arc<LoggerHandle>,
+}
 
 impl Drop for LoggerGuard {
     fn drop(&mut self) {
-        crate::Logger::finish();
+        self.logger.finish();
     }
 }
 
+/// Reply channel of a control command
+type Ack = crossbeam_channel::Sender<io::Result<()>>;
+
+/// Control commands processed by the backend thread in between log records
+enum Command {
+    /// Flushes every record queued before the command, acknowledging if a reply channel is given
+    Flush(Option<Ack>),
+    /// Closes the current file and starts a new one
+    Rotate(Ack),
+    /// Closes and reopens the current file
+    Reopen(Ack),
+    /// Drops records below the given level from now on
+    SetLevel(LogLevel, Ack),
+    /// Drains the queue, flushes and stops the backend thread
+    Shutdown(Ack),
+}
+
 pub struct Logger {
-    rc: RollingCondition,
-    folder: String,
-    prefix: String,
-    max_files: usize,
+    main_file: NamedFile,
+    flush_policy: FlushPolicy,
     cpu: Option<usize>,
     queue_size: usize,
     sleep_duration_nanos: u64,
     thread_name: String,
     set_std_log: bool,
     time_format_str: Option<String>,
-    sender: Option<crossbeam_channel::Sender<LoggingFunc>>,
-    status: Arc<AtomicU8>, /* 0->uninit, 1->inited, 2->require to flush, 3->require to stop, 4->stopped */
+    named_files: Vec<(String, NamedFile)>,
+    reopen_on_sighup: bool,
+    flush_on_crash: bool,
+    error_policy: ErrorPolicy,
 }
 
 impl Logger {
+    /// Stops the global logger, waiting at most `consts::FINISH_TIMEOUT` for it to drain.
     pub fn finish() {
-        let mut finish_flag = GLOBAL_LOGGER_STOP_FLAG.lock().unwrap();
-        // we can only finish logger once
-        if !(*finish_flag) {
-            *finish_flag = true;
-            GLOBAL_LOGGER
-                .get()
-                .unwrap()
-                .status
-                .store(3, std::sync::atomic::Ordering::Relaxed);
-            while GLOBAL_LOGGER
-                .get()
-                .unwrap()
-                .status
-                .load(std::sync::atomic::Ordering::Relaxed)
-                != 4
-            {
-                thread::sleep(Duration::from_micros(100));
-            }
+        if let Some(logger) = GLOBAL_LOGGER.get() {
+            logger.finish();
         }
     }
+
+    /// Stops the global logger after the queued records are written, or returns an error once
+    /// `timeout` expires or the backend thread is found dead, whose queued records are written to
+    /// stderr instead.
+    pub fn finish_with_timeout(timeout: Duration) -> io::Result<()> {
+        GLOBAL_LOGGER
+            .get()
+            .ok_or_else(LoggerHandle::stopped_error)?
+            .finish_with_timeout(timeout)
+    }
+
     pub fn flush() {
+        if let Some(logger) = GLOBAL_LOGGER.get() {
+            logger.flush();
+        }
+    }
+
+    /// Blocks until every record logged before this call has been written to the file (and
+    /// synced, if the flush policy has a sync interval), or until `timeout` expires.
+    pub fn flush_sync(timeout: Duration) -> io::Result<()> {
         GLOBAL_LOGGER
             .get()
-            .unwrap()
-            .status
-            .store(2, std::sync::atomic::Ordering::Relaxed);
+            .ok_or_else(LoggerHandle::stopped_error)?
+            .flush_sync(timeout)
+    }
+
+    /// Closes the current file and continues in a new one, regardless of the rolling condition.
+    pub fn rotate(timeout: Duration) -> io::Result<()> {
+        GLOBAL_LOGGER
+            .get()
+            .ok_or_else(LoggerHandle::stopped_error)?
+            .rotate(timeout)
+    }
+
+    /// Closes and reopens the current file, e.g. after it was moved away.
+    pub fn reopen(timeout: Duration) -> io::Result<()> {
+        GLOBAL_LOGGER
+            .get()
+            .ok_or_else(LoggerHandle::stopped_error)?
+            .reopen(timeout)
+    }
+
+    /// Drops records below `level` once this returns. Records logged before this call are not
+    /// affected.
+    ///
+    /// This only narrows the compile time `level-*` feature, it can not enable records it removed.
+    pub fn set_level(level: LogLevel, timeout: Duration) -> io::Result<()> {
+        GLOBAL_LOGGER
+            .get()
+            .ok_or_else(LoggerHandle::stopped_error)?
+            .set_level(level, timeout)
+    }
+
+    /// Logs every panic to the global logger at `Error` level with its message, location and
+    /// thread, and waits at most `consts::FINISH_TIMEOUT` until it and the records before it are
+    /// written. Then the previous hook runs, e.g. the default one printing to stderr, also when
+    /// there is nothing to log.
+    pub fn install_panic_hook() {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if let Some(logger) = GLOBAL_LOGGER.get() {
+                logger.log_panic(info);
+            }
+            previous(info);
+        }));
+    }
+
+    /// Counters of the global logger, if `Logger::init` was called
+    pub fn stats() -> Option<LoggerStats> {
+        GLOBAL_LOGGER.get().as_deref().map(LoggerHandle::stats)
     }
+
     pub fn new(rc: RollingCondition, folder: String, prefix: String) -> Self {
         Logger {
-            rc,
-            folder,
-            prefix,
-            max_files: consts::MAX_KEEP_FILE,
+            main_file: NamedFile::new(rc, folder, prefix),
+            flush_policy: FlushPolicy::new().idle(),
             cpu: None,
             set_std_log: false,
             time_format_str: None,
             queue_size: consts::MAX_QUEUE_SIZE,
             sleep_duration_nanos: consts::BACKGROUND_SLEEP_TIME_STEP_NANOS,
             thread_name: String::from("low_latency_log"),
-            sender: None,
-            status: Arc::new(AtomicU8::new(0)),
+            named_files: Vec::new(),
+            reopen_on_sighup: false,
+            flush_on_crash: false,
+            error_policy: ErrorPolicy::new(),
         }
     }
 
@@ -372,172 +1035,1048 @@ impl Logger {
         self
     }
 
+    /// Deletes the oldest files once there are more than the given number, counting the file
+    /// being written
     pub fn max_files(mut self, max_files: usize) -> Self {
-        self.max_files = max_files;
+        self.main_file.max_files = max_files;
         self
     }
 
-    pub fn queue_size(mut self, queue_size: usize) -> Self {
-        self.queue_size = queue_size;
+    /// Deletes the oldest files once all files together take up more than the given size,
+    /// the file being written is always kept
+    pub fn max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+        self.main_file.max_total_bytes = Some(max_total_bytes);
         self
     }
-    pub fn std_log(mut self, set: bool) -> Self {
-        self.set_std_log = set;
+
+    /// Deletes files last modified longer ago than the given duration
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.main_file.max_age = Some(max_age);
         self
     }
-    pub fn time_format_str(mut self, fmt: &str) -> Self {
-        self.time_format_str = Some(fmt.into());
+
+    /// Sets how rolled files are named, defaults to `FileNaming::Timestamp`
+    pub fn file_naming(mut self, file_naming: FileNaming) -> Self {
+        self.main_file.file_naming = file_naming;
         self
     }
-    pub fn background_sleep_time_step_nanos(mut self, nanos: u64) -> Self {
-        self.sleep_duration_nanos = nanos;
+
+    /// Compresses every file after it is rolled over, on a low priority thread of its own,
+    /// defaults to `Compression::None`
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.main_file.compression = compression;
         self
     }
 
-    pub fn init(mut self) -> io::Result<LoggerGuard> {
-        let (tx, rx) = match self.queue_size {
-            0 => crossbeam_channel::unbounded(),
-            _ => crossbeam_channel::bounded(self.queue_size),
-        };
+    /// Calls `f` with the closed and the new file after every rollover, on the same low
+    /// priority thread as `compression`. The closed file has been compressed by then.
+    pub fn on_rotate(mut self, f: impl Fn(&Path, &Path) + Send + Sync + 'static) -> Self {
+        self.main_file.on_rotate = Some(RotateHook::new(f));
+        self
+    }
 
-        self.sender = Some(tx);
+    /// Checks the free space of the folder before opening a file and about once a second
+    /// while writing, taking `action` while less than `min_free_bytes` are available, e.g. to
+    /// keep a log folder in `/dev/shm` from filling up memory
+    pub fn min_free_space(mut self, min_free_bytes: u64, action: LowSpaceAction) -> Self {
+        self.main_file.min_free_space = Some((min_free_bytes, action));
+        self
+    }
 
-        let time_fmt_str = if self.time_format_str.is_none() {
-            TIME_FORMAT_STR.into()
-        } else {
-            self.time_format_str.as_ref().unwrap().clone()
-        };
-        let mut rolling_logger = RollingLogger::new(
-            self.rc,
-            time_fmt_str,
-            self.folder.clone(),
-            self.prefix.clone(),
-            self.max_files,
-        );
+    /// Selects how records are written to the files, defaults to `WriterKind::Buffered`
+    pub fn writer(mut self, writer_kind: WriterKind) -> Self {
+        self.main_file.writer_kind = writer_kind;
+        self
+    }
 
-        let status = self.status.clone();
+    /// Copies every record into a ring buffer of `capacity` bytes mapped from the file at
+    /// `path`, e.g. under `/dev/shm`, as soon as it is formatted. The records survive a crash
+    /// or SIGKILL that loses the buffered ones, `read_flight_recorder` extracts them. Records
+    /// the backend has not taken from the queue yet are not formatted, so they are lost with
+    /// the process. Only available on unix.
+    pub fn flight_recorder(mut self, path: String, capacity: u64) -> Self {
+        self.main_file.flight_recorder = Some((path, capacity));
+        self
+    }
+
+    /// Selects what happens to records while a file can not be written, defaults to dropping
+    /// them, see `ErrorPolicy`
+    pub fn on_error(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Sets when the backend writes buffered records out, defaults to whenever the queue is idle
+    pub fn flush_policy(mut self, flush_policy: FlushPolicy) -> Self {
+        self.flush_policy = flush_policy;
+        self
+    }
+
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+    pub fn std_log(mut self, set: bool) -> Self {
+        self.set_std_log = set;
+        self
+    }
+    pub fn time_format_str(mut self, fmt: &str) -> Self {
+        self.time_format_str = Some(fmt.into());
+        self
+    }
+    pub fn background_sleep_time_step_nanos(mut self, nanos: u64) -> Self {
+        self.sleep_duration_nanos = nanos;
+        self
+    }
 
-        let _a = thread::Builder::new()
-            .name(self.thread_name.to_string())
+    /// Reopens every file when the process receives SIGHUP, like `Logger::reopen`. A SIGHUP
+    /// handler installed before is still called.
+    pub fn reopen_on_sighup(mut self, set: bool) -> Self {
+        self.reopen_on_sighup = set;
+        self
+    }
+
+    /// On SIGSEGV, SIGBUS and SIGABRT, writes a crash marker to stderr and waits at most
+    /// `consts::CRASH_DRAIN_TIMEOUT` for the backend to write the queued records, followed by
+    /// an error record naming the signal, before the signal is raised again. A handler
+    /// installed before runs afterwards. Only available on unix.
+    pub fn flush_on_crash(mut self, set: bool) -> Self {
+        self.flush_on_crash = set;
+        self
+    }
+
+    /// Adds a file that records logged through `Logger::named(name)` are written to, by the
+    /// same backend thread. The flush policy and time format are shared with the main file.
+    pub fn named_file(mut self, name: &str, file: NamedFile) -> Self {
+        self.named_files.push((name.into(), file));
+        self
+    }
+
+    /// The named file of the global logger registered as `name`, see `LoggerHandle::named`
+    pub fn named(name: &str) -> Option<NamedLogger<'static>> {
+        let logger = GLOBAL_LOGGER.get()?;
+        let logger_id = logger.named(name)?.logger_id;
+        Some(NamedLogger {
+            logger: HandleRef::Global(logger),
+            logger_id,
+        })
+    }
+
+    /// Starts the backend and installs it as the global logger used by the macros. A finished
+    /// global logger is replaced, a running one is an `AlreadyExists` error.
+    pub fn init(self) -> io::Result<LoggerGuard> {
+        let set_std_logger = self.set_std_log;
+        let logger = {
+            // replay records logged before init while holding the lock, so that records logged
+            // concurrently wait in `log_uninit` and stay ordered behind them
+            let mut state = UNINIT_STATE.lock().unwrap_or_else(|e| e.into_inner());
+            if GLOBAL_LOGGER
+                .get()
+                .is_some_and(|logger| !logger.is_finished())
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "the global logger is already initialized",
+                ));
+            }
+            let logger = Arc::new(self.start()?);
+            for func in state.buffered.drain(..) {
+                logger.log(func);
+            }
+            *GLOBAL_LOGGER_STOP_FLAG
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = false;
+            // the replaced logger is finished, it is freed once no thread refers to it
+            drop(GLOBAL_LOGGER.set(logger.clone()));
+            logger
+        };
+        if set_std_logger {
+            let fast_logger = log_proxy::LogProxy::default();
+            log::set_max_level(LogLevel::Info.into());
+            // the proxy forwards to whichever global logger is current, so keep the first one
+            let _ = log::set_boxed_logger(Box::new(fast_logger));
+        }
+        Ok(LoggerGuard { logger })
+    }
+
+    /// Starts a backend that is independent of the global logger, log to it with the
+    /// `logger: &handle` form of the macros. `std_log` only applies to `init`.
+    pub fn start(self) -> io::Result<LoggerHandle> {
+        if self.named_files.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many named files",
+            ));
+        }
+        let mut names: Vec<String> = Vec::with_capacity(self.named_files.len());
+        for (name, _) in &self.named_files {
+            if names.contains(name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("named file {} is registered twice", name),
+                ));
+            }
+            names.push(name.clone());
+        }
+
+        let (tx, rx) = match self.queue_size {
+            0 => crossbeam_channel::unbounded(),
+            _ => crossbeam_channel::bounded(self.queue_size),
+        };
+        let receiver = rx.clone();
+        let (control_tx, control_rx) = crossbeam_channel::unbounded();
+
+        let time_fmt_str = match &self.time_format_str {
+            Some(fmt) => fmt.clone(),
+            None => TIME_FORMAT_STR.into(),
+        };
+        let files = std::iter::once(self.main_file)
+            .chain(self.named_files.into_iter().map(|(_, file)| file));
+        let stats = Arc::<Stats>::default();
+        // one pair of rotation threads serves every file of the backend
+        let rotation = RotationThreads::start()?;
+        let mut rolling_loggers = Vec::with_capacity(names.len() + 1);
+        for file in files {
+            rolling_loggers.push(RollingLogger::build(
+                file,
+                time_fmt_str.clone(),
+                self.flush_policy,
+                self.error_policy.clone(),
+                stats.clone(),
+                &rotation,
+            )?);
+        }
+
+        let sighup_seen = match self.reopen_on_sighup {
+            true => Some(signal::watch_sighup()?),
+            false => None,
+        };
+        let crash_watch = match self.flush_on_crash {
+            true => Some(signal::watch_fatal_signals()?),
+            false => None,
+        };
+        let backend = Backend {
+            rx,
+            control_rx,
+            sighup_seen,
+            crash_watch,
+            rolling_loggers,
+            level: LogLevel::Trace,
+            sleep_duration: Duration::from_nanos(self.sleep_duration_nanos),
+        };
+        let cpu = self.cpu;
+
+        let backend_thread = thread::Builder::new()
+            .name(self.thread_name)
             .spawn(move || {
-                if let Some(core) = self.cpu {
+                if let Some(core) = cpu {
                     core_affinity::set_for_current(CoreId { id: core });
                 }
-                status.store(1, std::sync::atomic::Ordering::Relaxed); // set logger initted
-                loop {
-                    match rx.try_recv() {
-                        Ok(cmd) => {
-                            Self::process_log_command(cmd, &mut rolling_logger);
+                IS_BACKEND.set(true);
+                backend.run();
+            })?;
+
+        Ok(LoggerHandle {
+            sender: tx,
+            receiver: std::sync::Mutex::new(Some(receiver)),
+            control_sender: control_tx,
+            backend_thread,
+            finished: AtomicBool::new(false),
+            names,
+            stats,
+        })
+    }
+}
+
+/// A running logger, either the global one or one started with `Logger::start`. Dropping it
+/// finishes the logger.
+#[derive(Debug)]
+pub struct LoggerHandle {
+    sender: crossbeam_channel::Sender<LoggingFunc>,
+    /// Writes what a dead backend left in the queue to stderr, dropped once finished so the
+    /// queue disconnects when the backend stops
+    receiver: std::sync::Mutex<Option<crossbeam_channel::Receiver<LoggingFunc>>>,
+    control_sender: crossbeam_channel::Sender<Command>,
+    backend_thread: thread::JoinHandle<()>,
+    finished: AtomicBool,
+    names: Vec<String>,
+    stats: Arc<Stats>,
+}
+
+impl LoggerHandle {
+    /// A handle that writes to the file registered as `name` with `Logger::named_file`, use it
+    /// with the `logger: &named` form of the macros.
+    pub fn named(&self, name: &str) -> Option<NamedLogger<'_>> {
+        let index = self.names.iter().position(|n| n == name)?;
+        Some(NamedLogger {
+            logger: HandleRef::Borrowed(self),
+            logger_id: index as u8 + 1,
+        })
+    }
+
+    /// Stops the logger, waiting at most `consts::FINISH_TIMEOUT` for it to drain.
+    pub fn finish(&self) {
+        if let Err(e) = self.finish_with_timeout(consts::FINISH_TIMEOUT) {
+            eprintln!("WARNING: Failed to stop logger: {}", e);
+        }
+    }
+
+    /// Stops the logger after the queued records are written, or returns an error once
+    /// `timeout` expires or the backend thread is found dead. A dead backend's queued records are
+    /// written to stderr instead. Records logged afterwards follow the `UninitPolicy`.
+    pub fn finish_with_timeout(&self, timeout: Duration) -> io::Result<()> {
+        // we can only finish logger once
+        if self
+            .finished
+            .swap(true, std::sync::atomic::Ordering::AcqRel)
+        {
+            return Ok(());
+        }
+        if GLOBAL_LOGGER.is_current(self) {
+            *GLOBAL_LOGGER_STOP_FLAG
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = true;
+        }
+        let deadline = std::time::Instant::now() + timeout;
+        let mut res = self.send_command(Command::Shutdown, Some(timeout));
+        if res.is_err() && !self.backend_alive() {
+            res = Err(io::Error::other(format!(
+                "logger backend thread died, wrote {} queued records to stderr",
+                self.drain_dead_backend()
+            )));
+        }
+        // the rotation helpers of the exiting backend finish compressing and running the
+        // rotate hooks, which a process exiting right after this would cut short
+        while res.is_ok() && self.backend_alive() && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // the backend dropped its receiver before acknowledging, so records that raced with
+        // the shutdown are still queued, and once this receiver is gone too records logged
+        // from now on follow the `UninitPolicy`
+        let receiver = self
+            .receiver
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(receiver) = receiver.filter(|_| res.is_ok()) {
+            for func in receiver.try_iter() {
+                apply_uninit_policy(UNINIT_STATE.lock().unwrap_or_else(|e| e.into_inner()), func);
+            }
+        }
+        res
+    }
+
+    /// Whether `finish` was called on this logger
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Asks the backend to flush once the queue is drained, without waiting for it.
+    pub fn flush(&self) {
+        let _ = self.control_sender.send(Command::Flush(None));
+    }
+
+    /// Blocks until every record logged before this call has been written to the file (and
+    /// synced, if the flush policy has a sync interval), or until `timeout` expires.
+    pub fn flush_sync(&self, timeout: Duration) -> io::Result<()> {
+        self.send_command(|ack| Command::Flush(Some(ack)), Some(timeout))
+    }
+
+    /// Closes the current file and continues in a new one, regardless of the rolling condition.
+    pub fn rotate(&self, timeout: Duration) -> io::Result<()> {
+        self.send_command(Command::Rotate, Some(timeout))
+    }
+
+    /// Closes and reopens the current file, e.g. after it was moved away.
+    pub fn reopen(&self, timeout: Duration) -> io::Result<()> {
+        self.send_command(Command::Reopen, Some(timeout))
+    }
+
+    /// Drops records below `level` once this returns. Records logged before this call are not
+    /// affected.
+    pub fn set_level(&self, level: LogLevel, timeout: Duration) -> io::Result<()> {
+        self.send_command(|ack| Command::SetLevel(level, ack), Some(timeout))
+    }
+
+    /// Counters of this logger, they keep their values after it is finished.
+    pub fn stats(&self) -> LoggerStats {
+        self.stats.snapshot()
+    }
+
+    /// Logs a panic and waits until it is written, see `Logger::install_panic_hook`.
+    fn log_panic(&self, info: &std::panic::PanicHookInfo) {
+        // the backend logs panics of formatting closures itself
+        if IS_BACKEND.get() || self.is_finished() || LogLevel::Error < macros::LEVEL_FILTER {
+            return;
+        }
+        let location = info
+            .location()
+            .map_or_else(|| "<unknown>".into(), |l| l.to_string());
+        let msg = format!(
+            "thread '{}' panicked at {}: {}",
+            thread::current().name().unwrap_or("<unnamed>"),
+            location,
+            panic_message(info.payload())
+        );
+        self.log(LoggingFunc::new(
+            move || Cow::Owned(msg.clone()),
+            std::file!(),
+            std::line!(),
+            TID.get(),
+            LogLevel::Error,
+            unix_timestamp_ns(),
+        ));
+        if let Err(e) = self.flush_sync(consts::FINISH_TIMEOUT) {
+            eprintln!("WARNING: Failed to flush logger after panic: {}", e);
+        }
+    }
+
+    fn stopped_error() -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, "logger is stopped")
+    }
+
+    /// Sends a command to the backend and waits for its acknowledgement.
+    fn send_command(
+        &self,
+        make_command: impl FnOnce(Ack) -> Command,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        self.control_sender
+            .send(make_command(ack_tx))
+            .map_err(|_| Self::stopped_error())?;
+        let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
+        loop {
+            // wake up regularly so a dead backend is noticed even if the ack is never dropped
+            let wait = match deadline {
+                Some(deadline) => deadline
+                    .saturating_duration_since(std::time::Instant::now())
+                    .min(consts::BACKEND_LIVENESS_CHECK_INTERVAL),
+                None => consts::BACKEND_LIVENESS_CHECK_INTERVAL,
+            };
+            match ack_rx.recv_timeout(wait) {
+                Ok(res) => return res,
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                    return Err(Self::stopped_error())
+                }
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                    if !self.backend_alive() {
+                        self.drain_dead_backend();
+                        return Err(Self::stopped_error());
+                    }
+                    if deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "timed out waiting for logger backend",
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn backend_alive(&self) -> bool {
+        !self.backend_thread.is_finished()
+    }
+
+    /// Writes the records a dead backend left in the queue to stderr, returns how many.
+    fn drain_dead_backend(&self) -> usize {
+        match &*self.receiver.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some(receiver) => receiver.try_iter().map(|func| func.eprint()).count(),
+            None => 0,
+        }
+    }
+
+    pub fn log(&self, func: LoggingFunc) {
+        let res = match self.sender.try_send(func) {
+            Ok(()) => Ok(()),
+            // a backend that died can never drain the queue, so don't wait for it
+            Err(crossbeam_channel::TrySendError::Full(func)) if self.backend_alive() => {
+                self.sender.send(func).map_err(|e| (e.to_string(), e.0))
+            }
+            Err(e) => {
+                let err_msg = e.to_string();
+                Err((err_msg, e.into_inner()))
+            }
+        };
+        if let Err((err_msg, f)) = res {
+            if self.is_finished() {
+                // the logger was finished on purpose, this is not an error
+                let state = UNINIT_STATE.lock().unwrap_or_else(|e| e.into_inner());
+                return apply_uninit_policy(state, f);
+            }
+            // the records queued before this one go first
+            self.drain_dead_backend();
+            let lv = f.level.to_str();
+            let output_str = f.format_message();
+            eprintln!(
+                "Send to logger failed: e={}, log_msg={}:{} {} {}",
+                err_msg, f.file, f.line, lv, output_str
+            );
+        }
+    }
+}
+
+impl Drop for LoggerHandle {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Routes records to one of the named files of a logger
+#[derive(Clone, Debug)]
+pub struct NamedLogger<'a> {
+    logger: HandleRef<'a>,
+    logger_id: u8,
+}
+
+/// The logger a `NamedLogger` writes to, a global one is kept alive until it is dropped
+#[derive(Clone, Debug)]
+enum HandleRef<'a> {
+    Borrowed(&'a LoggerHandle),
+    Global(Arc<LoggerHandle>),
+}
+
+impl std::ops::Deref for HandleRef<'_> {
+    type Target = LoggerHandle;
+
+    fn deref(&self) -> &LoggerHandle {
+        match self {
+            HandleRef::Borrowed(logger) => logger,
+            HandleRef::Global(logger) => logger,
+        }
+    }
+}
+
+impl NamedLogger<'_> {
+    pub fn log(&self, mut func: LoggingFunc) {
+        func.logger_id = self.logger_id;
+        self.logger.log(func);
+    }
+}
+
+/// State owned by the backend thread
+struct Backend {
+    rx: crossbeam_channel::Receiver<LoggingFunc>,
+    control_rx: crossbeam_channel::Receiver<Command>,
+    /// The SIGHUP count files were last reopened at, if reopening on SIGHUP
+    sighup_seen: Option<u64>,
+    /// Set when draining the queue on a fatal signal, see `Logger::flush_on_crash`
+    crash_watch: Option<signal::CrashWatch>,
+    rolling_loggers: Vec<RollingLogger>, /* main file first, then the named files */
+    level: LogLevel,
+    sleep_duration: Duration,
+}
+
+impl Backend {
+    fn run(mut self) {
+        let mut processed: u32 = 0;
+        let shutdown = loop {
+            match self.rx.try_recv() {
+                Ok(cmd) => {
+                    self.process_log_command(cmd);
+                    processed = processed.wrapping_add(1);
+                    if processed.is_multiple_of(consts::CONTROL_CHECK_INTERVAL) {
+                        if let Some(shutdown) = self.process_control_commands() {
+                            break Some(shutdown);
+                        }
+                    }
+                }
+                Err(e) => {
+                    if let Some(shutdown) = self.process_control_commands() {
+                        break Some(shutdown);
+                    }
+                    match e {
+                        crossbeam_channel::TryRecvError::Empty => {
+                            let _ = self.for_each_file(RollingLogger::flush_when_idle);
+                            thread::sleep(self.sleep_duration);
                         }
-                        Err(e) => {
-                            let st = status.load(std::sync::atomic::Ordering::Relaxed);
-                            if st == 2 {
-                                // check if require to flush
-                                let _ = rolling_logger.flush();
-                                status.store(1, std::sync::atomic::Ordering::Relaxed);
-                            } else if st == 3 {
-                                // check if require to stop
-                                let _ = rolling_logger.flush();
-                                break;
-                            }
-                            match e {
-                                crossbeam_channel::TryRecvError::Empty => {
-                                    let _ = rolling_logger.flush();
-                                    thread::sleep(Duration::from_nanos(self.sleep_duration_nanos));
-                                }
-                                crossbeam_channel::TryRecvError::Disconnected => {
-                                    let _ = rolling_logger.flush();
-                                    break;
-                                }
-                            }
+                        crossbeam_channel::TryRecvError::Disconnected => {
+                            let _ = self.for_each_file(RollingLogger::flush_and_sync);
+                            break None;
                         }
                     }
                 }
-                status.store(4, std::sync::atomic::Ordering::Relaxed); // set logger stopped
-            });
+            }
+        };
+        // records that raced with the shutdown are left to the handle, see
+        // `LoggerHandle::finish_with_timeout`
+        drop(self.rx);
+        if let Some((ack, res)) = shutdown {
+            let _ = ack.send(res);
+        }
+        // answer the commands that raced with the shutdown, everything is flushed now
+        while let Ok(cmd) = self.control_rx.try_recv() {
+            match cmd {
+                Command::Flush(Some(ack)) | Command::Shutdown(ack) => {
+                    let _ = ack.send(Ok(()));
+                }
+                Command::Rotate(ack) | Command::Reopen(ack) | Command::SetLevel(_, ack) => {
+                    let _ = ack.send(Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "logger is stopped",
+                    )));
+                }
+                Command::Flush(None) => {}
+            }
+        }
+    }
 
-        let set_std_logger = self.set_std_log;
-        let _ = GLOBAL_LOGGER.set(self);
-        if set_std_logger {
-            let fast_logger = log_proxy::LogProxy::default();
-            log::set_max_level(LogLevel::Info.into());
-            log::set_boxed_logger(Box::new(fast_logger)).unwrap();
+    fn process_log_command(&mut self, cmd: LoggingFunc) {
+        if cmd.level < self.level {
+            return;
+        }
+        let (level, system_time) = (cmd.level, cmd.system_time);
+        let rolling_logger = &mut self.rolling_loggers[cmd.logger_id as usize];
+        rolling_logger.check_free_space_if_due(system_time);
+        if rolling_logger.drops_for_space(level) {
+            rolling_logger
+                .stats
+                .dropped_records
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            return;
+        }
+        if rolling_logger.failing(system_time) {
+            return rolling_logger.fall_back(&cmd);
         }
-        Ok(LoggerGuard)
+        rolling_logger.write_record(&cmd);
+        if rolling_logger.failure.is_some() {
+            return rolling_logger.fall_back(&cmd);
+        }
+        // a failed flush keeps the record buffered for the next attempt
+        let _ = rolling_logger.flush_after_record(level, system_time);
     }
 
-    fn process_log_command(cmd: LoggingFunc, rolling_logger: &mut RollingLogger) {
-        cmd.invoke(rolling_logger);
+    /// Applies `f` to every file, returning the first error after all of them were visited.
+    fn for_each_file(
+        &mut self,
+        mut f: impl FnMut(&mut RollingLogger) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut res = Ok(());
+        for rolling_logger in &mut self.rolling_loggers {
+            let r = f(rolling_logger);
+            if res.is_ok() {
+                res = r;
+            }
+        }
+        res
     }
 
-    pub fn log(&self, func: LoggingFunc) {
-        if let Some(tx) = &self.sender {
-            if let Err(e) = tx.send(func) {
-                let err_msg = e.to_string();
-                let f = e.0;
-                let lv = f.level.to_str();
-                let output = (f.func)();
-                let output_str = output.as_ref();
-                eprintln!(
-                    "Send to logger failed: e={}, log_msg={}:{} {} {}",
-                    err_msg, f.file, f.line, lv, output_str
-                );
+    /// Writes the queued records and a record naming the fatal signal, then lets the signal
+    /// handler go on.
+    fn drain_on_crash(&mut self, signum: i32) {
+        self.drain_queued();
+        let marker = format!("Received fatal signal {}, the process is crashing", signum);
+        self.process_log_command(LoggingFunc::new(
+            move || Cow::Owned(marker.clone()),
+            std::file!(),
+            std::line!(),
+            TID.get(),
+            LogLevel::Error,
+            unix_timestamp_ns(),
+        ));
+        let _ = self.for_each_file(RollingLogger::flush_and_sync);
+        if let Some(crash_watch) = &self.crash_watch {
+            crash_watch.drained();
+        }
+    }
+
+    /// Records sent before a command are already queued when it arrives, so draining the
+    /// current queue length preserves their order and cannot livelock under a steady producer.
+    fn drain_queued(&mut self) {
+        for _ in 0..self.rx.len() {
+            match self.rx.try_recv() {
+                Ok(cmd) => self.process_log_command(cmd),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Processes pending control commands, returns the acknowledgement of a shutdown request
+    /// together with the result of the final flush.
+    fn process_control_commands(&mut self) -> Option<(Ack, io::Result<()>)> {
+        if let Some(seen) = self.sighup_seen {
+            let count = signal::sighup_count();
+            if count != seen {
+                self.sighup_seen = Some(count);
+                if let Err(e) = self.for_each_file(RollingLogger::reopen) {
+                    eprintln!("WARNING: Failed to reopen logfile on SIGHUP: {}", e);
+                }
             }
         }
+        if let Some(signum) = self
+            .crash_watch
+            .as_mut()
+            .and_then(signal::CrashWatch::take_signal)
+        {
+            self.drain_on_crash(signum);
+        }
+        while let Ok(cmd) = self.control_rx.try_recv() {
+            match cmd {
+                Command::Flush(ack) => {
+                    self.drain_queued();
+                    let res = self.for_each_file(RollingLogger::flush_and_sync);
+                    if let Some(ack) = ack {
+                        let _ = ack.send(res);
+                    }
+                }
+                Command::Rotate(ack) => {
+                    self.drain_queued();
+                    let res = self.for_each_file(RollingLogger::rotate);
+                    let _ = ack.send(res);
+                }
+                Command::Reopen(ack) => {
+                    self.drain_queued();
+                    let _ = ack.send(self.for_each_file(RollingLogger::reopen));
+                }
+                Command::SetLevel(level, ack) => {
+                    self.drain_queued();
+                    self.level = level;
+                    let _ = ack.send(Ok(()));
+                }
+                Command::Shutdown(ack) => {
+                    self.drain_queued();
+                    let res = self.for_each_file(RollingLogger::close);
+                    return Some((ack, res));
+                }
+            }
+        }
+        None
     }
 }
 
 impl RollingLogger {
+    /// Hands the buffered records to the writer, the io_uring writer may still be writing them
+    /// when this returns.
     fn flush(&mut self) -> io::Result<()> {
         if let Some(writer) = self.writer_buffer.as_mut() {
-            writer.flush()?;
+            if let Err(e) = writer.submit() {
+                return Err(self.fail(e, unix_timestamp_ns()));
+            }
+            if self.flushed_file_size != self.current_file_size {
+                self.flushed_file_size = self.current_file_size;
+                self.need_sync = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self, now_ns: u64) -> io::Result<()> {
+        self.last_sync_ns = now_ns;
+        if let Some(writer) = self.writer_buffer.as_mut() {
+            if self.need_sync {
+                self.need_sync = false;
+                if let Err(e) = writer.sync_data() {
+                    return Err(self.fail(e, now_ns));
+                }
+            }
         }
         Ok(())
     }
+
+    /// Flushes, waits until the records are in the file and syncs when the policy asks for
+    /// durability.
+    fn flush_and_sync(&mut self) -> io::Result<()> {
+        if let Some(fallback) = self.fallback.as_mut() {
+            let _ = fallback.flush_and_sync();
+        }
+        self.flush()?;
+        if let Some(writer) = self.writer_buffer.as_mut() {
+            if let Err(e) = writer.flush() {
+                return Err(self.fail(e, unix_timestamp_ns()));
+            }
+        }
+        if self.flush_policy.sync_interval_opt.is_some() {
+            self.sync(unix_timestamp_ns())?;
+        }
+        Ok(())
+    }
+
+    fn flush_after_record(&mut self, level: LogLevel, now_ns: u64) -> io::Result<()> {
+        let policy = &self.flush_policy;
+        let should_flush = policy.level_opt.is_some_and(|lvl| level >= lvl)
+            || policy
+                .max_bytes_opt
+                .is_some_and(|n| self.current_file_size - self.flushed_file_size >= n)
+            || policy
+                .interval_opt
+                .is_some_and(|d| now_ns.saturating_sub(self.last_flush_ns) >= d.as_nanos() as u64);
+        if should_flush {
+            self.flush_and_sync_if_due(now_ns)?;
+        }
+        Ok(())
+    }
+
+    fn flush_when_idle(&mut self) -> io::Result<()> {
+        let now_ns = unix_timestamp_ns();
+        if let Some(fallback) = self.fallback.as_mut() {
+            let _ = fallback.flush_when_idle();
+        }
+        if self.failing(now_ns) {
+            return Ok(());
+        }
+        let policy = &self.flush_policy;
+        if self.current_file_size != self.flushed_file_size
+            && (policy.idle
+                || policy.interval_opt.is_some_and(|d| {
+                    now_ns.saturating_sub(self.last_flush_ns) >= d.as_nanos() as u64
+                }))
+        {
+            return self.flush_and_sync_if_due(now_ns);
+        }
+        if let Some(d) = self.flush_policy.sync_interval_opt {
+            if self.need_sync && now_ns.saturating_sub(self.last_sync_ns) >= d.as_nanos() as u64 {
+                self.sync(now_ns)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_and_sync_if_due(&mut self, now_ns: u64) -> io::Result<()> {
+        self.flush()?;
+        self.last_flush_ns = now_ns;
+        if let Some(d) = self.flush_policy.sync_interval_opt {
+            if now_ns.saturating_sub(self.last_sync_ns) >= d.as_nanos() as u64 {
+                self.sync(now_ns)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes, closes the file and waits for the rotation helper to move it into place, so
+    /// every record is in a file with its final length before the logger is reported as
+    /// stopped. No file is opened afterwards.
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(fallback) = self.fallback.as_mut() {
+            let _ = fallback.close();
+        }
+        self.flush_and_sync()?;
+        if let Some(writer) = self.writer_buffer.take() {
+            writer.into_file()?;
+        }
+        self.rotation_helper.wait_placed();
+        self.rotation_helper.discard_pending();
+        Ok(())
+    }
+
+    /// Closes the current file and opens a new one right away, returning once the rotation
+    /// helper moved the new one into place. Compressing the closed one is left to the helper.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.rollover()?;
+        self.open_writer_if_needed(&Local::now())?;
+        self.rotation_helper.wait_placed();
+        Ok(())
+    }
+
+    /// Formats and writes a record. With a `max_size` the mmap writer gets the whole record at
+    /// once, after rolling over if it would not fit into the current file, so its files stay
+    /// within the preallocated region unless a single record is larger than `max_size`. The
+    /// buffered writer checks the size once a second.
+    fn write_record(&mut self, cmd: &LoggingFunc) {
+        let max_size = match self.condition.max_size_opt {
+            Some(max_size) if self.writer_kind == WriterKind::Mmap => max_size,
+            _ => return cmd.invoke(self),
+        };
+        self.staging = true;
+        cmd.invoke(self);
+        self.staging = false;
+        let record = std::mem::take(&mut self.record_buf);
+        let len = u64::try_from(record.len()).unwrap_or(u64::MAX);
+        if self.current_file_size > 0 && self.current_file_size.saturating_add(len) > max_size {
+            let res = self
+                .rollover()
+                .and_then(|_| self.open_writer_if_needed(&Local::now()));
+            if let Err(e) = res {
+                eprintln!("WARNING: Failed to rotate logfile  {}", e);
+            }
+        }
+        let _ = self.write_to_buffer(&record);
+        self.record_buf = record;
+        self.record_buf.clear();
+    }
+
+    /// Closes the current file, the next write opens a new one. Syncing and closing the file
+    /// is left to the rotation helper.
     pub fn rollover(&mut self) -> io::Result<()> {
         self.flush()?;
-        // We must close the current file before rotating files
-        self.writer_buffer.take();
+        if let Some(writer) = self.writer_buffer.take() {
+            let need_sync = self.flush_policy.sync_interval_opt.is_some() && self.need_sync;
+            self.need_sync = false;
+            let file = writer.into_file()?;
+            self.closed_file = Some((file, need_sync));
+        }
         self.current_file_size = 0;
+        self.flushed_file_size = 0;
         Ok(())
     }
 
-    fn new_file_name(&self, now: &DateTime<Local>) -> String {
-        let mut str = String::with_capacity(self.prefix.len() + 16);
-        str.push_str(self.prefix.as_str());
-        str.push('.');
-        str.push_str(now.format("%Y%m%d.%H%M%S").to_string().as_str());
+    fn new_file_name(&mut self, now: &DateTime<Local>) -> String {
+        let (mut str, after_index) = self.template.render(now);
+        if let Some(after_index) = after_index {
+            let index = self.next_index(&str, &after_index);
+            str.push_str(index.to_string().as_str());
+            str.push_str(after_index.as_str());
+        }
         str
     }
-    /// Opens a writer for the current file.
+
+    /// Returns the index following the last file named `stem` + index + `suffix`, looking at the folder
+    /// the first time a stem is seen so a restarted process never reuses a file.
+    fn next_index(&mut self, stem: &str, suffix: &str) -> u64 {
+        if let Some((last_stem, index)) = self.last_index.as_mut() {
+            if last_stem == stem {
+                *index += 1;
+                return *index;
+            }
+        }
+        let max_index = std::fs::read_dir(&self.folder)
+            .map(|files| {
+                files
+                    .flatten()
+                    .filter_map(|f| {
+                        let fname = f.file_name();
+                        // compressed files keep their index even with compression turned off
+                        let fname = compress::strip_compressed_extension(fname.to_str()?);
+                        let index = fname.strip_prefix(stem)?.strip_suffix(suffix)?;
+                        index.parse::<u64>().ok()
+                    })
+                    .max()
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0);
+        self.last_index = Some((stem.to_string(), max_index + 1));
+        max_index + 1
+    }
+    /// Opens a writer for the current file, swapping in the file the rotation helper pre-opened
+    /// where possible.
     fn open_writer_if_needed(&mut self, now: &DateTime<Local>) -> io::Result<()> {
-        if self.writer_buffer.is_none() {
-            let p = self.new_file_name(now);
-            let new_file_path = std::path::Path::new(&self.folder).join(&p);
-            if std::fs::metadata(&self.folder).is_err() {
-                std::fs::create_dir_all(&self.folder)?;
-            }
-            let f = std::fs::OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(&new_file_path)?;
-            self.writer_buffer = Some(BufWriter::with_capacity(1024 * 1024, f));
-            // make a soft link to latest file
-            {
-                let folder = std::path::Path::new(&self.folder);
-                if let Ok(path) = folder.canonicalize() {
-                    let latest_log_symlink = path.join(&self.prefix);
-                    let _ = remove_symlink_auto(folder.join(&self.prefix));
-                    let _ = symlink_auto(new_file_path.canonicalize().unwrap(), latest_log_symlink);
-                }
+        if self.writer_buffer.is_some() {
+            return Ok(());
+        }
+        self.open_writer(now)
+            .map_err(|e| self.fail(e, unix_timestamp_ns()))
+    }
+
+    fn open_writer(&mut self, now: &DateTime<Local>) -> io::Result<()> {
+        let new_file_name = self.new_file_name(now);
+        let new_file_path = Path::new(&self.folder).join(new_file_name);
+        self.check_free_space(Some(new_file_path.clone()));
+
+        // two rollovers within the same second may name the file that was just closed
+        if self.current_file_path.as_ref() == Some(&new_file_path) {
+            if let Some((file, need_sync)) = self.closed_file.take() {
+                let writer = LogFileWriter::append(self.writer_kind, file, self.preallocate)?;
+                self.current_file_size = writer.len();
+                self.flushed_file_size = self.current_file_size;
+                self.need_sync = need_sync;
+                self.writer_buffer = Some(writer);
+                return Ok(());
             }
-            self.current_file_size = std::fs::metadata(&p).map_or(0, |m| m.len());
-            self.check_and_remove_log_file()?;
         }
+
+        // an existing file is appended to, so it must not be replaced by the pending one
+        let pending = match fs::symlink_metadata(&new_file_path) {
+            Ok(_) => None,
+            Err(_) => self.rotation_helper.take_pending(),
+        };
+        // the closed file is only handed over once the new one is open, a failed open keeps it
+        // for the next attempt
+        let (writer, pending_path) = match pending {
+            Some(PendingFile { file, path }) => (
+                LogFileWriter::new(self.writer_kind, file, self.preallocate)?,
+                Some(path),
+            ),
+            None => {
+                if std::fs::metadata(&self.folder).is_err() {
+                    std::fs::create_dir_all(&self.folder)?;
+                }
+                let file = Self::create_file(&new_file_path)?;
+                let writer = LogFileWriter::append(self.writer_kind, file, self.preallocate)?;
+                (writer, None)
+            }
+        };
+        self.current_file_size = writer.len();
+        self.writer_buffer = Some(writer);
+        let closed_file_path = self
+            .current_file_path
+            .replace(new_file_path.clone())
+            .filter(|path| *path != new_file_path);
+        let closed = self.closed_file.take();
+        self.flushed_file_size = self.current_file_size;
+        self.rotation_helper.opened(Opened {
+            closed,
+            closed_file_path,
+            new_path: new_file_path,
+            pending_path,
+        });
         Ok(())
     }
 
+    fn create_file(path: &Path) -> io::Result<File> {
+        // the mmap writer needs read access to map the file
+        std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+    }
+
+    /// Closes the current file and opens the same path again, recreating it if it is gone.
+    /// Records the old file did not take, e.g. because its disk is full, move to the new one.
+    fn reopen(&mut self) -> io::Result<()> {
+        let _ = self.flush_and_sync();
+        // the current file may still have to be moved into place
+        self.rotation_helper.wait_placed();
+        match self.current_file_path.clone() {
+            Some(path) => {
+                // the mmap writer cuts its file to the records when closed, which must not
+                // happen once the same file is mapped again
+                let mut unflushed = None;
+                if self.writer_kind == WriterKind::Mmap {
+                    unflushed = self
+                        .writer_buffer
+                        .take()
+                        .and_then(LogFileWriter::into_unwritten);
+                }
+                let res = match std::fs::metadata(&self.folder) {
+                    Ok(_) => Ok(()),
+                    Err(_) => std::fs::create_dir_all(&self.folder),
+                };
+                let writer = res
+                    .and_then(|_| Self::create_file(&path))
+                    .and_then(|file| {
+                        LogFileWriter::append(self.writer_kind, file, self.preallocate)
+                    })
+                    .map_err(|e| self.fail(e, unix_timestamp_ns()))?;
+                self.current_file_size = writer.len();
+                let unflushed = self
+                    .writer_buffer
+                    .replace(writer)
+                    .and_then(LogFileWriter::into_unwritten)
+                    .or(unflushed);
+                self.flushed_file_size = self.current_file_size;
+                // the symlink may point at the file that was moved away, and the pending file
+                // may be gone with its folder
+                self.rotation_helper.opened(Opened {
+                    closed: None,
+                    closed_file_path: None,
+                    new_path: path,
+                    pending_path: None,
+                });
+                self.rotation_helper.renew_pending();
+                match unflushed {
+                    Some(unflushed) => self.write_to_file(&unflushed),
+                    None => Ok(()),
+                }
+            }
+            None => self.open_writer_if_needed(&Local::now()),
+        }
+    }
+
     pub fn rollate_with_datetime(&mut self, time_point: &DateTime<Local>) -> io::Result<()> {
+        if let Err(e) = self.reopen_if_deleted() {
+            eprintln!("WARNING: Failed to reopen deleted logfile {}", e);
+        }
         if self
             .condition
             .should_rollover(time_point, self.current_file_size)
@@ -550,13 +2089,222 @@ impl RollingLogger {
         Ok(())
     }
 
-    pub fn write_to_buffer(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let writer = self.writer_buffer.as_mut().unwrap();
-        let buf_len = buf.len();
-        writer.write_all(buf).map(|_| {
-            self.current_file_size += u64::try_from(buf_len).unwrap_or(u64::MAX);
-            buf_len
-        })
+    fn check_free_space_if_due(&mut self, now_ns: u64) {
+        if self.min_free_space.is_some() && now_ns >= self.next_space_check_ns {
+            self.next_space_check_ns =
+                now_ns.saturating_add(consts::FREE_SPACE_CHECK_INTERVAL.as_nanos() as u64);
+            self.check_free_space(self.current_file_path.clone());
+        }
+    }
+
+    /// Compares the free space of the folder with `min_free_space`, asking the rotation helper
+    /// to delete old files but `current` or limiting records to warnings while it is short.
+    fn check_free_space(&mut self, current: Option<PathBuf>) {
+        let Some((min_free, action)) = self.min_free_space else {
+            return;
+        };
+        // a folder that is gone has to be recreated first, see `reopen_if_deleted`
+        let Ok(available) = free_space::available_space(Path::new(&self.folder)) else {
+            return;
+        };
+        let low_space = available < min_free;
+        if low_space != self.low_space {
+            self.low_space = low_space;
+            match low_space {
+                true => eprintln!(
+                    "WARNING: Only {} bytes are free for {}, {}",
+                    available,
+                    self.folder,
+                    match action {
+                        LowSpaceAction::DeleteOldest => "deleting the oldest logfiles",
+                        LowSpaceAction::WarningsOnly => "dropping records below warnings",
+                    }
+                ),
+                false => eprintln!("WARNING: Enough space is free for {} again", self.folder),
+            }
+        }
+        if low_space && action == LowSpaceAction::DeleteOldest {
+            if let Some(current) = current {
+                self.rotation_helper.free_space(min_free, current);
+            }
+        }
+    }
+
+    /// Whether a record at `level` is dropped to save space.
+    fn drops_for_space(&self, level: LogLevel) -> bool {
+        self.low_space
+            && level < LogLevel::Warn
+            && self
+                .min_free_space
+                .is_some_and(|(_, action)| action == LowSpaceAction::WarningsOnly)
+    }
+
+    /// Opens the current path again if the file or its folder was deleted underneath us, moving
+    /// the records that were not written out yet to the new file. Runs once a second, see
+    /// `rollate_with_datetime`, so what was flushed to the deleted file in between is lost.
+    fn reopen_if_deleted(&mut self) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let deleted = self
+                .writer_buffer
+                .as_ref()
+                .and_then(|w| w.file().metadata().ok())
+                .is_some_and(|m| m.nlink() == 0);
+            if !deleted {
+                return Ok(());
+            }
+            if let Some(writer) = self.writer_buffer.take() {
+                let unwritten = writer.into_unwritten();
+                self.reopen()?;
+                if let Some(unwritten) = unwritten {
+                    self.write_to_file(&unwritten)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_to_buffer(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.staging {
+            self.record_buf.extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.write(buf);
+        }
+        self.write_to_file(buf).map(|_| buf.len())
+    }
+
+    /// Writes `buf` to the file only, for records that were copied to the flight recorder
+    /// already, e.g. those moved to a reopened file.
+    fn write_to_file(&mut self, buf: &[u8]) -> io::Result<()> {
+        let res = match self.writer_buffer.as_mut() {
+            Some(writer) => writer.write_all(buf),
+            None => Err(Self::not_open_error()),
+        };
+        match res {
+            Ok(()) => {
+                self.current_file_size += u64::try_from(buf.len()).unwrap_or(u64::MAX);
+                Ok(())
+            }
+            Err(e) => Err(self.fail(e, unix_timestamp_ns())),
+        }
+    }
+
+    fn not_open_error() -> io::Error {
+        io::Error::new(io::ErrorKind::NotConnected, "no logfile is open")
+    }
+
+    /// Whether records have to go to the fallback, trying the file again once the backoff
+    /// expired.
+    fn failing(&mut self, now_ns: u64) -> bool {
+        let Some(failure) = &self.failure else {
+            return false;
+        };
+        if now_ns < failure.retry_at_ns {
+            return true;
+        }
+        let res = match self.writer_buffer.is_some() {
+            true => self.flush(),
+            false => self.open_writer_if_needed(&Local::now()),
+        };
+        if res.is_err() {
+            return true;
+        }
+        eprintln!(
+            "WARNING: Logfile {} can be written again",
+            self.display_path()
+        );
+        self.failure = None;
+        false
+    }
+
+    fn display_path(&self) -> Cow<'_, str> {
+        self.current_file_path
+            .as_deref()
+            .unwrap_or(Path::new(&self.folder))
+            .to_string_lossy()
+    }
+
+    /// Counts `e` and makes records go to the fallback until the backoff expired, returns `e`.
+    fn fail(&mut self, e: io::Error, now_ns: u64) -> io::Error {
+        self.stats
+            .write_errors
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let policy = &self.error_policy;
+        match &mut self.failure {
+            // several writes of the same record may fail, only a failed retry extends the backoff
+            Some(failure) if now_ns < failure.retry_at_ns => {}
+            Some(failure) => {
+                failure.backoff = (failure.backoff * 2).min(policy.max_retry_backoff);
+                failure.retry_at_ns = now_ns.saturating_add(failure.backoff.as_nanos() as u64);
+            }
+            None => {
+                eprintln!(
+                    "WARNING: Failed to write logfile {}, using the fallback until it works again: {}",
+                    self.display_path(),
+                    e
+                );
+                self.failure = Some(Failure {
+                    retry_at_ns: now_ns.saturating_add(policy.retry_backoff.as_nanos() as u64),
+                    backoff: policy.retry_backoff,
+                });
+                self.open_fallback();
+            }
+        }
+        e
+    }
+
+    /// Writes a record that its file did not take to the fallback.
+    fn fall_back(&mut self, func: &LoggingFunc) {
+        let stats = self.stats.clone();
+        let written = match &self.error_policy.fallback {
+            Fallback::Drop => false,
+            Fallback::Stderr => {
+                func.eprint();
+                true
+            }
+            Fallback::Folder(_) => match self.fallback.as_deref_mut() {
+                Some(fallback) => {
+                    !fallback.failing(func.system_time) && {
+                        fallback.write_record(func);
+                        fallback.failure.is_none()
+                    }
+                }
+                None => false,
+            },
+        };
+        let counter = match written {
+            true => &stats.fallback_records,
+            false => &stats.dropped_records,
+        };
+        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Opens the file in the fallback folder unless it is open, every failure tries again.
+    fn open_fallback(&mut self) {
+        if self.fallback.is_some() {
+            return;
+        }
+        if let Some(file) = self.fallback_file.clone() {
+            let res = RollingLogger::build(
+                file,
+                self.time_fmt_str.clone(),
+                self.flush_policy,
+                ErrorPolicy::new().retry_backoff(
+                    self.error_policy.retry_backoff,
+                    self.error_policy.max_retry_backoff,
+                ),
+                self.stats.clone(),
+                self.rotation_helper.threads(),
+            );
+            match res {
+                Ok(fallback) => self.fallback = Some(Box::new(fallback)),
+                Err(e) => eprintln!("WARNING: Failed to open fallback logfile: {}", e),
+            }
+        }
     }
 
     pub fn write_date_time_str(&mut self, unix_timestamp_ns: u64) {
@@ -567,7 +2315,8 @@ impl RollingLogger {
                 // if cached timestamp is not the same as now
                 let local_date_time =
                     DateTime::from_timestamp_nanos(unix_timestamp_ns as i64).with_timezone(&Local);
-                let _ = self.rollate_with_datetime(&local_date_time); // rollate if needed
+                // rollate if needed, a file that can not be opened fails the writes below
+                let _ = self.rollate_with_datetime(&local_date_time);
                 {
                     // update cached date time
                     let cached = &mut self.cached_date_time;
@@ -577,42 +2326,12 @@ impl RollingLogger {
                         .to_string();
                 }
             }
-            self.cached_date_time.1.as_bytes()
+            std::mem::take(&mut self.cached_date_time.1)
         };
-        let writer = self.writer_buffer.as_mut().unwrap();
-        let _ = writer.write_all(data_str_array).map(|_| {
-            self.current_file_size += u64::try_from(data_str_array.len()).unwrap_or(u64::MAX);
-        });
-
-        uwrite!(self, ".{} ", unix_timestamp_ns - (now_sec * 1_000_000_000)).unwrap();
-    }
-
-    fn check_and_remove_log_file(&mut self) -> io::Result<()> {
-        let files = std::fs::read_dir(&self.folder)?;
-
-        let mut log_files = vec![];
-        for f in files.flatten() {
-            let fname = f.file_name().to_string_lossy().to_string();
-            if fname.starts_with(&self.prefix) && fname != self.prefix {
-                log_files.push(fname);
-            }
-        }
-
-        log_files.sort_by(|a, b| b.cmp(a));
+        let _ = self.write_to_buffer(data_str_array.as_bytes());
+        self.cached_date_time.1 = data_str_array;
 
-        if log_files.len() > self.max_files {
-            for f in log_files.drain(self.max_files..) {
-                let p = Path::new(&self.folder).join(f);
-                if let Err(e) = fs::remove_file(&p) {
-                    eprintln!(
-                        "WARNING: Failed to remove old logfile {}: {}",
-                        p.to_string_lossy(),
-                        e
-                    );
-                }
-            }
-        }
-        Ok(())
+        let _ = uwrite!(self, ".{} ", unix_timestamp_ns - (now_sec * 1_000_000_000));
     }
 }
 
@@ -641,24 +2360,65 @@ impl RollingLogger {
     }
     #[inline]
     fn write_u32(&mut self, n: u32) -> Result<(), std::io::Error> {
-        let writer_buffer = self.writer_buffer.as_mut().unwrap();
+        let writer_buffer = self
+            .writer_buffer
+            .as_mut()
+            .ok_or_else(Self::not_open_error)?;
         fmt_utils::write_u32(n, writer_buffer)
     }
 }
 
-impl Drop for Logger {
-    fn drop(&mut self) {
-        Self::finish();
+/// Compares file names so that embedded numbers are ordered by value, e.g. `log.9` < `log.10`,
+/// breaks ties between files with the same timestamp and index
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (a_num, a_rest) = a.split_at(a_len);
+                let (b_num, b_rest) = b.split_at(b_len);
+                let a_num = &a_num[a_num.iter().take_while(|c| **c == b'0').count()..];
+                let b_num = &b_num[b_num.iter().take_while(|c| **c == b'0').count()..];
+                let ord = a_num.len().cmp(&b_num.len()).then(a_num.cmp(b_num));
+                if ord != std::cmp::Ordering::Equal {
+                    return ord;
+                }
+                (a, b) = (a_rest, b_rest);
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                (a, b) = (&a[1..], &b[1..]);
+            }
+        }
     }
 }
 
-pub fn logger() -> &'static Logger {
-    GLOBAL_LOGGER.get().unwrap()
+fn unix_timestamp_ns() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+/// The global logger, panics if `Logger::init` was not called. See `GLOBAL_LOGGER` for a
+/// version that does not.
+pub fn logger() -> Arc<LoggerHandle> {
+    GLOBAL_LOGGER
+        .get()
+        .expect("the global logger is not initialized")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::{self, TempDir};
 
     #[test]
     fn test_log_func_size() {
@@ -666,4 +2426,1034 @@ mod tests {
         println!("The size of LoggingFunc is: {}", size);
         assert!(size <= 64);
     }
+
+    #[test]
+    fn test_with_writer_formats_in_place() {
+        let func = LoggingFunc::with_writer(
+            |w: &mut LogWriter| uwrite!(w, "id={} ok={}", 42u32, true),
+            file!(),
+            line!(),
+            "0",
+            LogLevel::Info,
+            0,
+        );
+        assert_eq!(func.format_message(), "id=42 ok=true");
+    }
+
+    #[test]
+    fn test_write_fmt_formats_in_place() {
+        let pi = 3.5f64;
+        let func = LoggingFunc::with_writer(
+            move |w: &mut LogWriter| internal::write_fmt(w, format_args!("pi={} {:>4}", pi, "x")),
+            file!(),
+            line!(),
+            "0",
+            LogLevel::Info,
+            0,
+        );
+        assert_eq!(func.format_message(), "pi=3.5    x");
+    }
+
+    #[test]
+    fn test_formatting_panic_is_caught() {
+        let func = LoggingFunc::with_writer(
+            |w: &mut LogWriter| {
+                w.write_str("partial ")?;
+                panic!("broken Display impl")
+            },
+            file!(),
+            line!(),
+            "0",
+            LogLevel::Info,
+            0,
+        );
+        assert_eq!(
+            func.format_message(),
+            "partial <formatting panicked: broken Display impl>"
+        );
+    }
+
+    #[test]
+    fn test_backend_survives_formatting_panic() {
+        struct Broken;
+        impl std::fmt::Display for Broken {
+            fn fmt(&self, _: &mut std::fmt::Formatter) -> std::fmt::Result {
+                panic!("broken Display impl")
+            }
+        }
+
+        let folder = TempDir::new("formatting_panic");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Fixed)
+            .start()
+            .unwrap();
+        crate::info!(logger: &handle, "{}", Broken);
+        crate::info!(logger: &handle, "after");
+        handle.flush_sync(Duration::from_secs(5)).unwrap();
+
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("<formatting panicked: broken Display impl>"));
+        assert!(lines[1].ends_with("INFO after"));
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn test_flush_policy() {
+        let folder = TempDir::new("flush_policy");
+        let mut rolling_logger = RollingLogger::new(
+            RollingCondition::new(),
+            TIME_FORMAT_STR.into(),
+            folder.to_folder(),
+            "test".into(),
+            1,
+        );
+        rolling_logger.flush_policy = FlushPolicy::new().max_bytes(8).level(LogLevel::Warn);
+        let file_len = || fs::metadata(folder.join("test")).unwrap().len();
+
+        rolling_logger.write_to_buffer(b"abcd").unwrap();
+        rolling_logger
+            .flush_after_record(LogLevel::Info, 0)
+            .unwrap();
+        assert_eq!(file_len(), 0);
+        rolling_logger.write_to_buffer(b"efgh").unwrap();
+        rolling_logger
+            .flush_after_record(LogLevel::Info, 0)
+            .unwrap();
+        assert_eq!(file_len(), 8);
+        rolling_logger.write_to_buffer(b"ij").unwrap();
+        rolling_logger
+            .flush_after_record(LogLevel::Error, 0)
+            .unwrap();
+        assert_eq!(file_len(), 10);
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        use std::cmp::Ordering;
+        assert_eq!(natural_cmp("log.9", "log.10"), Ordering::Less);
+        assert_eq!(
+            natural_cmp("log.20240101.2", "log.20240101.10"),
+            Ordering::Less
+        );
+        assert_eq!(
+            natural_cmp("log.20240102.1", "log.20240101.10"),
+            Ordering::Greater
+        );
+        assert_eq!(natural_cmp("log.010", "log.10"), Ordering::Equal);
+        assert_eq!(natural_cmp("log.a", "log.b"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_index_naming_never_reuses_a_file() {
+        let folder = TempDir::new("index");
+        let file = NamedFile::new(
+            RollingCondition::new().max_size(4),
+            folder.to_folder(),
+            "test".into(),
+        )
+        .file_naming(FileNaming::Index);
+        let mut rolling_logger = RollingLogger::build(
+            file,
+            TIME_FORMAT_STR.into(),
+            FlushPolicy::new(),
+            ErrorPolicy::new(),
+            Arc::default(),
+            &RotationThreads::start().unwrap(),
+        )
+        .unwrap();
+        let now = Local::now();
+        for _ in 0..2 {
+            rolling_logger.write_to_buffer(b"12345").unwrap();
+            rolling_logger.rollate_with_datetime(&now).unwrap();
+        }
+        // dropping the logger waits for the rotation helper
+        drop(rolling_logger);
+
+        for (name, len) in [("test.1", 5), ("test.2", 5), ("test.3", 0)] {
+            assert_eq!(fs::metadata(folder.join(name)).unwrap().len(), len);
+        }
+    }
+
+    #[test]
+    fn test_retention_by_total_bytes() {
+        let folder = TempDir::new("retention");
+        fs::write(folder.join("test.other"), b"not ours").unwrap();
+        let file = NamedFile::new(
+            RollingCondition::new().max_size(4),
+            folder.to_folder(),
+            "test".into(),
+        )
+        .file_naming(FileNaming::Index)
+        .max_total_bytes(10);
+        let mut rolling_logger = RollingLogger::build(
+            file,
+            TIME_FORMAT_STR.into(),
+            FlushPolicy::new(),
+            ErrorPolicy::new(),
+            Arc::default(),
+            &RotationThreads::start().unwrap(),
+        )
+        .unwrap();
+        let now = Local::now();
+        for _ in 0..4 {
+            rolling_logger.write_to_buffer(b"12345").unwrap();
+            rolling_logger.rollate_with_datetime(&now).unwrap();
+        }
+        drop(rolling_logger);
+
+        let mut names: Vec<_> = fs::read_dir(&folder)
+            .unwrap()
+            .map(|f| f.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["test", "test.3", "test.4", "test.5", "test.other"]);
+    }
+
+    #[test]
+    fn test_retention_by_age() {
+        let folder = TempDir::new("retention_age");
+        let hour_ago = std::time::SystemTime::now() - Duration::from_secs(3600);
+        for name in ["test.1", "test.2", "other"] {
+            let file = File::create(folder.join(name)).unwrap();
+            file.set_modified(hour_ago).unwrap();
+        }
+        fs::write(folder.join("test.3"), b"recent\n").unwrap();
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Index)
+            .max_age(Duration::from_secs(60))
+            .start()
+            .unwrap();
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        let mut names: Vec<_> = fs::read_dir(&folder)
+            .unwrap()
+            .map(|f| f.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["other", "test", "test.3", "test.4"]);
+    }
+
+    #[test]
+    fn test_retention_ignores_compressed_files_without_compression() {
+        let folder = TempDir::new("retention_compressed");
+        for name in ["test.1.gz", "test.2.zst"] {
+            fs::write(folder.join(name), b"old\n").unwrap();
+        }
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Index)
+            .max_files(1)
+            .start()
+            .unwrap();
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        let mut names: Vec<_> = fs::read_dir(&folder)
+            .unwrap()
+            .map(|f| f.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["test", "test.1.gz", "test.2.zst", "test.3"]);
+    }
+
+    #[test]
+    fn test_on_rotate() {
+        let folder = TempDir::new("on_rotate");
+        let rotated = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let file = NamedFile::new(
+            RollingCondition::new().max_size(4),
+            folder.to_folder(),
+            "test".into(),
+        )
+        .file_naming(FileNaming::Index)
+        .on_rotate({
+            let rotated = rotated.clone();
+            move |closed: &Path, new: &Path| {
+                rotated
+                    .lock()
+                    .unwrap()
+                    .push((closed.to_path_buf(), new.to_path_buf()));
+                assert!(closed.exists());
+            }
+        });
+        let mut rolling_logger = RollingLogger::build(
+            file,
+            TIME_FORMAT_STR.into(),
+            FlushPolicy::new(),
+            ErrorPolicy::new(),
+            Arc::default(),
+            &RotationThreads::start().unwrap(),
+        )
+        .unwrap();
+        let now = Local::now();
+        for _ in 0..2 {
+            rolling_logger.write_to_buffer(b"12345").unwrap();
+            rolling_logger.rollate_with_datetime(&now).unwrap();
+        }
+        drop(rolling_logger);
+
+        let rotated = rotated.lock().unwrap();
+        assert_eq!(
+            *rotated,
+            [
+                (folder.join("test.1"), folder.join("test.2")),
+                (folder.join("test.2"), folder.join("test.3")),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recover_pending_files_of_dead_processes() {
+        let folder = TempDir::new("recover_pending");
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        let own = std::process::id();
+        // pid 1 always runs, and u64::MAX stands for another logger of this process
+        for (pid, id) in [(dead, 0), (1, 0), (own, u64::MAX)] {
+            let name = format!(".test.next.{}.{}", pid, id);
+            fs::write(folder.join(name), b"record\n").unwrap();
+        }
+        fs::write(folder.join(format!(".test.next.{}.1", dead)), b"").unwrap();
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Fixed)
+            .start()
+            .unwrap();
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        let names: Vec<_> = fs::read_dir(&folder)
+            .unwrap()
+            .map(|f| f.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(names.len(), 4);
+        let recovered = format!(".test.next.{}.0.recovered.", dead);
+        assert!(names.iter().any(|name| name.starts_with(&recovered)));
+        assert!(names.contains(&".test.next.1.0".into()));
+        assert!(names.contains(&format!(".test.next.{}.{}", own, u64::MAX)));
+    }
+
+    #[test]
+    fn test_rotate_does_not_wait_for_the_rotate_hook() {
+        let folder = TempDir::new("rotate_hook_flush");
+        let handle_slot = Arc::new(std::sync::OnceLock::<std::sync::Weak<LoggerHandle>>::new());
+        let hook_results = Arc::new(std::sync::Mutex::new(vec![]));
+        let handle = Arc::new(
+            Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+                .file_naming(FileNaming::Index)
+                .on_rotate({
+                    let handle_slot = handle_slot.clone();
+                    let hook_results = hook_results.clone();
+                    move |_: &Path, _: &Path| {
+                        let handle = handle_slot.get().and_then(std::sync::Weak::upgrade);
+                        if let Some(handle) = handle {
+                            let res = handle.flush_sync(Duration::from_secs(5));
+                            hook_results.lock().unwrap().push(res.is_ok());
+                        }
+                    }
+                })
+                .start()
+                .unwrap(),
+        );
+        handle_slot.set(Arc::downgrade(&handle)).unwrap();
+
+        let start = std::time::Instant::now();
+        for i in 0..3 {
+            crate::info!(logger: &*handle, "record {}", i);
+            handle.rotate(Duration::from_secs(5)).unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+        while hook_results.lock().unwrap().len() < 3 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(*hook_results.lock().unwrap(), [true, true, true]);
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_error_policy_fallback() {
+        let folder = TempDir::new("error");
+        let fallback = TempDir::new("fallback");
+        // every write to /dev/full fails with ENOSPC
+        std::os::unix::fs::symlink("/dev/full", folder.join("test")).unwrap();
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Fixed)
+            .on_error(
+                ErrorPolicy::new()
+                    .fallback(Fallback::Folder(fallback.to_folder()))
+                    .retry_backoff(Duration::from_secs(60), Duration::from_secs(60)),
+            )
+            .start()
+            .unwrap();
+
+        crate::info!(logger: &handle, "buffered");
+        assert!(handle.flush_sync(Duration::from_secs(5)).is_err());
+        crate::info!(logger: &handle, "to fallback");
+        assert!(handle.finish_with_timeout(Duration::from_secs(5)).is_err());
+
+        let stats = handle.stats();
+        assert!(stats.write_errors > 0);
+        assert_eq!(stats.fallback_records, 1);
+        assert_eq!(stats.dropped_records, 0);
+        let content = fs::read_to_string(fallback.join("test")).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.trim_end().ends_with("INFO to fallback"));
+    }
+
+    #[test]
+    fn test_min_free_space() {
+        let folder = TempDir::new("free_space");
+        let folder_str = folder.to_folder();
+        for name in ["test.1", "test.2", "other"] {
+            fs::write(folder.join(name), b"old\n").unwrap();
+        }
+        // no file system ever has u64::MAX bytes free
+        let handle = Logger::new(RollingCondition::new(), folder_str.clone(), "test".into())
+            .file_naming(FileNaming::Index)
+            .min_free_space(u64::MAX, LowSpaceAction::DeleteOldest)
+            .named_file(
+                "quiet",
+                NamedFile::new(RollingCondition::new(), folder_str, "quiet".into())
+                    .min_free_space(u64::MAX, LowSpaceAction::WarningsOnly),
+            )
+            .start()
+            .unwrap();
+        let quiet = handle.named("quiet").unwrap();
+
+        crate::info!(logger: &handle, "kept");
+        crate::info!(logger: &quiet, "dropped");
+        crate::warn!(logger: &quiet, "kept");
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        assert!(!folder.join("test.1").exists());
+        assert!(!folder.join("test.2").exists());
+        assert!(folder.join("other").exists());
+        let content = fs::read_to_string(folder.join("test.3")).unwrap();
+        assert!(content.trim_end().ends_with("INFO kept"));
+        let content = fs::read_to_string(folder.join("quiet")).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.trim_end().ends_with("WARN kept"));
+        assert_eq!(handle.stats().dropped_records, 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_files_share_the_rotation_threads() {
+        if !test_util::in_child() {
+            let output = test_util::run_in_child("tests::test_files_share_the_rotation_threads");
+            return test_util::assert_child_succeeded(&output);
+        }
+        let threads_named = |name: &str| {
+            fs::read_dir("/proc/self/task")
+                .unwrap()
+                .filter(|task| {
+                    let comm = fs::read_to_string(task.as_ref().unwrap().path().join("comm"));
+                    comm.is_ok_and(|comm| comm.trim_end() == name)
+                })
+                .count()
+        };
+        let folder = TempDir::new("shared_rotation");
+        let mut logger = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Index);
+        for name in ["a", "b", "c"] {
+            let file = NamedFile::new(RollingCondition::new(), folder.to_folder(), name.into())
+                .file_naming(FileNaming::Index);
+            logger = logger.named_file(name, file);
+        }
+        let handle = logger.start().unwrap();
+        assert_eq!(threads_named("lll-rotation"), 1);
+        assert_eq!(threads_named("lll-cleanup"), 1);
+
+        for name in ["a", "b", "c"] {
+            crate::info!(logger: &handle.named(name).unwrap(), "to {}", name);
+        }
+        handle.rotate(Duration::from_secs(5)).unwrap();
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(threads_named("lll-rotation"), 0);
+
+        for name in ["a", "b", "c"] {
+            let content = fs::read_to_string(folder.join(format!("{}.1", name))).unwrap();
+            assert!(content.ends_with(&format!("INFO to {}\n", name)));
+        }
+        // every pre-opened file was removed
+        let hidden = fs::read_dir(&folder)
+            .unwrap()
+            .filter(|f| {
+                f.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with('.')
+            })
+            .count();
+        assert_eq!(hidden, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mmap_writer_rolls_over_by_record() {
+        let folder = TempDir::new("mmap_rollover");
+        let handle = Logger::new(
+            RollingCondition::new().max_size(100),
+            folder.to_folder(),
+            "test".into(),
+        )
+        .file_naming(FileNaming::Index)
+        .writer(WriterKind::Mmap)
+        .start()
+        .unwrap();
+
+        for i in 0..5 {
+            crate::info!(logger: &handle, "record {}", i);
+        }
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        // records are about 60 bytes, a second one does not fit into the 100 preallocated
+        for i in 1..=5 {
+            let content = fs::read_to_string(folder.join(format!("test.{}", i))).unwrap();
+            assert!(!content.contains('\0'));
+            assert!(content.len() <= 100);
+            assert_eq!(content.lines().count(), 1);
+        }
+        assert!(!folder.join("test.6").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mmap_writer_reopens_the_same_file() {
+        let folder = TempDir::new("mmap_reopen");
+        let handle = Logger::new(
+            RollingCondition::new().max_size(1 << 20),
+            folder.to_folder(),
+            "test".into(),
+        )
+        .file_naming(FileNaming::Fixed)
+        .writer(WriterKind::Mmap)
+        .start()
+        .unwrap();
+
+        crate::info!(logger: &handle, "before");
+        handle.reopen(Duration::from_secs(5)).unwrap();
+        // mapped up to max_size again, not cut under the new mapping
+        assert_eq!(fs::metadata(folder.join("test")).unwrap().len(), 1 << 20);
+        // well past the first page
+        for i in 0..200 {
+            crate::info!(logger: &handle, "record {}", i);
+        }
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        assert!(!content.contains('\0'));
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 201);
+        assert!(lines[0].ends_with("INFO before"));
+        assert!(lines[200].ends_with("INFO record 199"));
+    }
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    #[test]
+    fn test_io_uring_writer() {
+        let folder = TempDir::new("io_uring");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Index)
+            .writer(WriterKind::IoUring)
+            .start()
+            .unwrap();
+
+        for i in 0..1000 {
+            crate::info!(logger: &handle, "record {}", i);
+        }
+        handle.flush_sync(Duration::from_secs(5)).unwrap();
+        let content = fs::read_to_string(folder.join("test.1")).unwrap();
+        assert_eq!(content.lines().count(), 1000);
+
+        crate::info!(logger: &handle, "rotated");
+        handle.rotate(Duration::from_secs(5)).unwrap();
+        crate::info!(logger: &handle, "last");
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+        let content = fs::read_to_string(folder.join("test.1")).unwrap();
+        assert!(content.ends_with("INFO rotated\n"));
+        let content = fs::read_to_string(folder.join("test.2")).unwrap();
+        assert!(content.ends_with("INFO last\n"));
+    }
+
+    #[test]
+    fn test_panic_is_logged_and_flushed() {
+        if !test_util::in_child() {
+            let output = test_util::run_in_child("tests::test_panic_is_logged_and_flushed");
+            test_util::assert_child_succeeded(&output);
+            // also after the logger finished and the hook has nothing to log
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert_eq!(stderr.matches("previous hook ran").count(), 2);
+            return;
+        }
+        let folder = TempDir::new("panic");
+        let guard = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .init()
+            .unwrap();
+        std::panic::set_hook(Box::new(|_| eprintln!("previous hook ran")));
+        Logger::install_panic_hook();
+
+        let panic_in_worker = || {
+            let res = thread::Builder::new()
+                .name("panicking worker".into())
+                .spawn(|| {
+                    for i in 0..100 {
+                        crate::info!("record {}", i);
+                    }
+                    panic!("boom");
+                })
+                .unwrap()
+                .join();
+            assert!(res.is_err());
+        };
+        panic_in_worker();
+
+        // written before the thread finished panicking, without flushing here
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 101);
+        assert!(lines[99].ends_with("INFO record 99"));
+        assert!(lines[100].contains("ERROR thread 'panicking worker' panicked at src/lib.rs:"));
+        assert!(lines[100].ends_with(": boom"));
+
+        drop(guard);
+        panic_in_worker();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_flush_on_crash() {
+        let folder = TempDir::new("crash");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .flush_on_crash(true)
+            .start()
+            .unwrap();
+
+        for i in 0..100 {
+            crate::info!(logger: &handle, "record {}", i);
+        }
+        assert!(signal::simulate_crash(libc::SIGSEGV));
+
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 101);
+        assert!(lines[99].ends_with("INFO record 99"));
+        assert!(lines[100].ends_with("ERROR Received fatal signal 11, the process is crashing"));
+
+        // every crash waits for its own drain
+        crate::info!(logger: &handle, "record 100");
+        assert!(signal::simulate_crash(libc::SIGBUS));
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 103);
+        assert!(lines[101].ends_with("INFO record 100"));
+        assert!(lines[102].ends_with("ERROR Received fatal signal 7, the process is crashing"));
+
+        handle.finish();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_abort_is_flushed() {
+        use std::os::unix::process::ExitStatusExt;
+
+        if !test_util::in_child() {
+            let folder = TempDir::new("abort");
+            let output = test_util::run_in_child("tests::test_abort_is_flushed");
+            assert_eq!(output.status.signal(), Some(libc::SIGABRT));
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains("FATAL: Received signal 6, flushing logs"));
+
+            let content = fs::read_to_string(folder.join("test")).unwrap();
+            let lines: Vec<&str> = content.lines().collect();
+            assert_eq!(lines.len(), 101);
+            assert!(lines[99].ends_with("INFO record 99"));
+            assert!(lines[100].ends_with("ERROR Received fatal signal 6, the process is crashing"));
+            return;
+        }
+        // without an idle flush, records only reach the file because of the crash
+        let handle = Logger::new(
+            RollingCondition::new(),
+            test_util::parent_temp_dir("abort").to_string_lossy().into(),
+            "test".into(),
+        )
+        .flush_policy(FlushPolicy::new())
+        .flush_on_crash(true)
+        .start()
+        .unwrap();
+        for i in 0..100 {
+            crate::info!(logger: &handle, "record {}", i);
+        }
+        std::process::abort();
+    }
+
+    #[test]
+    fn test_flush_sync() {
+        let folder = TempDir::new("flush_sync");
+        // without an idle flush, records only reach the file when asked to
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .flush_policy(FlushPolicy::new().sync_interval(Duration::from_secs(60)))
+            .start()
+            .unwrap();
+
+        for i in 0..100 {
+            crate::info!(logger: &handle, "record {}", i);
+        }
+        handle.flush_sync(Duration::from_secs(5)).unwrap();
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        assert_eq!(content.lines().count(), 100);
+        assert!(content.ends_with("INFO record 99\n"));
+
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn test_set_level() {
+        let folder = TempDir::new("set_level");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .start()
+            .unwrap();
+
+        crate::info!(logger: &handle, "before");
+        handle
+            .set_level(LogLevel::Warn, Duration::from_secs(5))
+            .unwrap();
+        crate::info!(logger: &handle, "dropped");
+        crate::warn!(logger: &handle, "after");
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("INFO before"));
+        assert!(lines[1].ends_with("WARN after"));
+    }
+
+    #[test]
+    fn test_rotate_and_reopen() {
+        let folder = TempDir::new("rotate_reopen");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Index)
+            .start()
+            .unwrap();
+
+        crate::info!(logger: &handle, "first");
+        handle.rotate(Duration::from_secs(5)).unwrap();
+        crate::info!(logger: &handle, "second");
+        handle.flush_sync(Duration::from_secs(5)).unwrap();
+        // like logrotate, which moves the file away and then asks for it to be reopened
+        fs::rename(folder.join("test.2"), folder.join("moved")).unwrap();
+        handle.reopen(Duration::from_secs(5)).unwrap();
+        crate::info!(logger: &handle, "third");
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        for (name, msg) in [
+            ("test.1", "first"),
+            ("moved", "second"),
+            ("test.2", "third"),
+        ] {
+            let content = fs::read_to_string(folder.join(name)).unwrap();
+            assert_eq!(content.lines().count(), 1);
+            assert!(content.ends_with(&format!("INFO {}\n", msg)));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reopen_on_sighup() {
+        if !test_util::in_child() {
+            let output = test_util::run_in_child("tests::test_reopen_on_sighup");
+            return test_util::assert_child_succeeded(&output);
+        }
+        let folder = TempDir::new("sighup");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Fixed)
+            .reopen_on_sighup(true)
+            .start()
+            .unwrap();
+
+        crate::info!(logger: &handle, "before");
+        handle.flush_sync(Duration::from_secs(5)).unwrap();
+        // like logrotate, which moves the file away and then sends SIGHUP
+        fs::rename(folder.join("test"), folder.join("moved")).unwrap();
+        // SAFETY: the logger installed a handler, so SIGHUP does not terminate the process
+        unsafe { libc::raise(libc::SIGHUP) };
+        let start = std::time::Instant::now();
+        while !folder.join("test").exists() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+        crate::info!(logger: &handle, "after");
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        for (name, msg) in [("moved", "before"), ("test", "after")] {
+            let content = fs::read_to_string(folder.join(name)).unwrap();
+            assert_eq!(content.lines().count(), 1);
+            assert!(content.ends_with(&format!("INFO {}\n", msg)));
+        }
+    }
+
+    #[test]
+    fn test_command_times_out() {
+        let folder = TempDir::new("command_timeout");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Fixed)
+            .start()
+            .unwrap();
+
+        // keeps the backend busy past the timeout of the commands below
+        handle.log(LoggingFunc::new(
+            || {
+                thread::sleep(Duration::from_millis(500));
+                Cow::Borrowed("slow")
+            },
+            file!(),
+            line!(),
+            "0",
+            LogLevel::Info,
+            unix_timestamp_ns(),
+        ));
+        let timeout = Duration::from_millis(50);
+        for res in [
+            handle.rotate(timeout),
+            handle.reopen(timeout),
+            handle.set_level(LogLevel::Warn, timeout),
+        ] {
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        }
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        assert!(content.ends_with("INFO slow\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reopened_records_are_recorded_once() {
+        let folder = TempDir::new("recorder_reopen");
+        let recorder = folder.join("recorder");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .file_naming(FileNaming::Fixed)
+            .flush_policy(FlushPolicy::new())
+            .flight_recorder(recorder.to_string_lossy().into(), 4096)
+            .start()
+            .unwrap();
+
+        // "first" is still buffered when the file is deleted, the next second moves it to the
+        // reopened file
+        crate::info!(logger: &handle, "first");
+        // a round trip to the backend that does not flush
+        handle
+            .set_level(LogLevel::Info, Duration::from_secs(5))
+            .unwrap();
+        fs::remove_file(folder.join("test")).unwrap();
+        handle.log(LoggingFunc::new(
+            || Cow::Borrowed("second"),
+            file!(),
+            line!(),
+            "0",
+            LogLevel::Info,
+            unix_timestamp_ns() + 2_000_000_000,
+        ));
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        let records = read_flight_recorder(&recorder, 10).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].ends_with("INFO first"));
+        assert!(records[1].ends_with("INFO second"));
+    }
+
+    #[test]
+    fn test_dead_backend_is_drained_to_stderr() {
+        let folder = TempDir::new("dead_backend");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .start()
+            .unwrap();
+
+        // a record for a named file that does not exist makes the backend panic
+        let mut func = LoggingFunc::new(
+            || Cow::Borrowed("kills the backend"),
+            file!(),
+            line!(),
+            "0",
+            LogLevel::Info,
+            unix_timestamp_ns(),
+        );
+        func.logger_id = 1;
+        handle.log(func);
+        while handle.backend_alive() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        for i in 0..3 {
+            crate::info!(logger: &handle, "queued {}", i);
+        }
+        let e = handle
+            .finish_with_timeout(Duration::from_secs(5))
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "logger backend thread died, wrote 3 queued records to stderr"
+        );
+    }
+
+    #[test]
+    fn test_uninit_policy_drop() {
+        if !test_util::in_child() {
+            let output = test_util::run_in_child("tests::test_uninit_policy_drop");
+            return test_util::assert_child_succeeded(&output);
+        }
+        let folder = TempDir::new("uninit_drop");
+        crate::info!("before init");
+        assert_eq!(uninit_dropped_count(), 1);
+        let guard = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .init()
+            .unwrap();
+        crate::info!("logged");
+        drop(guard);
+        crate::info!("after finish");
+        assert_eq!(uninit_dropped_count(), 2);
+
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.ends_with("INFO logged\n"));
+    }
+
+    #[test]
+    fn test_uninit_policy_buffer() {
+        if !test_util::in_child() {
+            let output = test_util::run_in_child("tests::test_uninit_policy_buffer");
+            return test_util::assert_child_succeeded(&output);
+        }
+        let folder = TempDir::new("uninit_buffer");
+        set_uninit_policy(UninitPolicy::Buffer(3));
+        for i in 0..4 {
+            crate::info!("early {}", i);
+        }
+        assert_eq!(uninit_dropped_count(), 1);
+        // shrinking the buffer drops the newest records
+        set_uninit_policy(UninitPolicy::Buffer(2));
+        assert_eq!(uninit_dropped_count(), 2);
+        let guard = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .init()
+            .unwrap();
+        crate::info!("late");
+        drop(guard);
+
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("INFO early 0"));
+        assert!(lines[1].ends_with("INFO early 1"));
+        assert!(lines[2].ends_with("INFO late"));
+    }
+
+    #[test]
+    fn test_uninit_policy_stderr() {
+        if !test_util::in_child() {
+            let output = test_util::run_in_child("tests::test_uninit_policy_stderr");
+            test_util::assert_child_succeeded(&output);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains("INFO to stderr 1\n"));
+            return;
+        }
+        set_uninit_policy(UninitPolicy::Stderr);
+        crate::info!("to stderr {}", 1);
+        assert_eq!(uninit_dropped_count(), 0);
+    }
+
+    #[test]
+    fn test_init_twice() {
+        if !test_util::in_child() {
+            let output = test_util::run_in_child("tests::test_init_twice");
+            return test_util::assert_child_succeeded(&output);
+        }
+        let first = TempDir::new("init_first");
+        let second = TempDir::new("init_second");
+        let guard = Logger::new(RollingCondition::new(), first.to_folder(), "test".into())
+            .init()
+            .unwrap();
+        let e = Logger::new(RollingCondition::new(), second.to_folder(), "test".into())
+            .init()
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        crate::info!("to first");
+        let first_logger = Arc::downgrade(&logger());
+        drop(guard);
+        assert!(*GLOBAL_LOGGER_STOP_FLAG.lock().unwrap());
+
+        let guard = Logger::new(RollingCondition::new(), second.to_folder(), "test".into())
+            .init()
+            .unwrap();
+        assert!(!*GLOBAL_LOGGER_STOP_FLAG.lock().unwrap());
+        crate::info!("to second");
+        // freed once this thread, the only one that logged to it, logged to the new one
+        assert!(first_logger.upgrade().is_none());
+        drop(guard);
+
+        for (folder, msg) in [(&first, "to first"), (&second, "to second")] {
+            let content = fs::read_to_string(folder.join("test")).unwrap();
+            assert_eq!(content.lines().count(), 1);
+            assert!(content.ends_with(&format!("INFO {}\n", msg)));
+        }
+    }
+
+    #[test]
+    fn test_independent_logger_handle() {
+        let folder = TempDir::new("handle");
+        let handle = Logger::new(RollingCondition::new(), folder.to_folder(), "test".into())
+            .start()
+            .unwrap();
+
+        crate::info!(logger: &handle, "hello {}", 1);
+        crate::uwarn!(logger: &handle, "hello {}", 2u32);
+        crate::error!(logger: &handle, "bye");
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+        assert!(handle.is_finished());
+
+        let content = fs::read_to_string(folder.join("test")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("INFO hello 1"));
+        assert!(lines[1].ends_with("WARN hello 2"));
+        assert!(lines[2].ends_with("ERROR bye"));
+    }
+
+    #[test]
+    fn test_named_files() {
+        let folder = TempDir::new("named");
+        let folder_str = folder.to_folder();
+        let handle = Logger::new(RollingCondition::new(), folder_str.clone(), "main".into())
+            .named_file(
+                "orders",
+                NamedFile::new(RollingCondition::new(), folder_str.clone(), "orders".into()),
+            )
+            .named_file(
+                "audit",
+                NamedFile::new(RollingCondition::new(), folder_str, "audit".into()).max_files(2),
+            )
+            .start()
+            .unwrap();
+        assert!(handle.named("missing").is_none());
+        let orders = handle.named("orders").unwrap();
+        let audit = handle.named("audit").unwrap();
+
+        crate::info!(logger: &handle, "to main");
+        crate::info!(logger: &orders, "to orders {}", 1);
+        crate::uinfo!(logger: &audit, "to audit {}", 2u8);
+        handle.finish_with_timeout(Duration::from_secs(5)).unwrap();
+
+        for (prefix, msg) in [
+            ("main", "to main"),
+            ("orders", "to orders 1"),
+            ("audit", "to audit 2"),
+        ] {
+            let content = fs::read_to_string(folder.join(prefix)).unwrap();
+            assert_eq!(content.lines().count(), 1);
+            assert!(content.trim_end().ends_with(msg));
+        }
+    }
 }
@@ -21,10 +21,10 @@ impl log::Log for LogProxy {
             Some(s) => Cow::Borrowed(s),
             None => Cow::Owned(record.args().to_string()),
         };
-        let func = crate::internal::LoggingFunc::new(
-            move || args.clone(),
+        let func = crate::internal::LoggingFunc::with_writer(
+            move |w: &mut crate::LogWriter| w.write_str(args.as_ref()),
             record.file_static().unwrap_or(""),
-            record.line().unwrap_or(0) as u32,
+            record.line().unwrap_or(0),
             tid,
             lvl,
             system_time,
@@ -1,14 +1,20 @@
 pub const LEVEL_FILTER: crate::LogLevel = LEVEL_FILTER_INNER;
 
+/// Logs a record to the global logger, or to the given `LoggerHandle` or `NamedLogger` with
+/// `log!(logger: &handle, ...)`.
 #[macro_export]
 macro_rules! log {
-    ($lvl:expr, $fmt:expr, $($arg:tt)+) => {
+    (logger: $logger:expr, $lvl:expr, $($arg:tt)+) => {
+        $crate::log!(@send (|func| ($logger).log(func)), $lvl, $($arg)+)
+    };
+
+    (@send $send:expr, $lvl:expr, $fmt:expr, $($arg:tt)+) => {
         if $lvl >= $crate::macros::LEVEL_FILTER{
             let tid = $crate::TID.get();
             let system_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64;
-            let func = $crate::internal::LoggingFunc::new(
-                move || {
-                    format!($fmt, $($arg)+).into()
+            let func = $crate::internal::LoggingFunc::with_writer(
+                move |w: &mut $crate::LogWriter| {
+                    $crate::internal::write_fmt(w, format_args!($fmt, $($arg)+))
                 },
                 std::file!(),
                 std::line!(),
@@ -16,11 +22,11 @@ macro_rules! log {
                 $lvl,
                 system_time,
             );
-            $crate::internal::log(func);
+            ($send)(func);
         }
     };
 
-    ($lvl:expr, $fmt:expr) => {
+    (@send $send:expr, $lvl:expr, $fmt:expr) => {
         if $lvl >= $crate::macros::LEVEL_FILTER{
             let tid = $crate::TID.get();
             let system_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64;
@@ -34,9 +40,45 @@ macro_rules! log {
                 $lvl,
                 system_time
             );
-            $crate::internal::log(func);
+            ($send)(func);
+        }
+    };
+
+    ($lvl:expr, $($arg:tt)+) => {
+        $crate::log!(@send $crate::internal::log, $lvl, $($arg)+)
+    };
+}
+
+/// Like [`log!`], but formats with `ufmt` directly into the backend's buffer, so no
+/// intermediate `String` is allocated. Arguments must implement `ufmt::uDisplay`/`uDebug`.
+#[macro_export]
+macro_rules! ulog {
+    (logger: $logger:expr, $lvl:expr, $($arg:tt)+) => {
+        $crate::ulog!(@send (|func| ($logger).log(func)), $lvl, $($arg)+)
+    };
+
+    (@send $send:expr, $lvl:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {
+        if $lvl >= $crate::macros::LEVEL_FILTER{
+            let tid = $crate::TID.get();
+            let system_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64;
+            let func = $crate::internal::LoggingFunc::with_writer(
+                move |w: &mut $crate::LogWriter| {
+                    use $crate::ufmt;
+                    $crate::ufmt::uwrite!(w, $fmt $(, $arg)*)
+                },
+                std::file!(),
+                std::line!(),
+                tid,
+                $lvl,
+                system_time,
+            );
+            ($send)(func);
         }
     };
+
+    ($lvl:expr, $($arg:tt)+) => {
+        $crate::ulog!(@send $crate::internal::log, $lvl, $($arg)+)
+    };
 }
 
 use cfg_if::cfg_if;
@@ -61,12 +103,14 @@ cfg_if! {
 
 #[macro_export]
 macro_rules! error {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::log!(logger: $logger, $crate::LogLevel::Error, $($arg)+));
     ($($arg:tt)+) => ($crate::log!($crate::LogLevel::Error, $($arg)+));
     ($fmt:expr) => ($crate::log!($crate::Level::Error, expr))
 }
 
 #[macro_export]
 macro_rules! warn {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::log!(logger: $logger, $crate::LogLevel::Warn, $($arg)+));
     ($($arg:tt)+) => ($crate::log!($crate::LogLevel::Warn, $($arg)+));
     ($fmt:expr) => ($crate::log!($crate::Level::Warn, expr))
 
@@ -74,18 +118,51 @@ macro_rules! warn {
 
 #[macro_export]
 macro_rules! info {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::log!(logger: $logger, $crate::LogLevel::Info, $($arg)+));
     ($($arg:tt)+) => ($crate::log!($crate::LogLevel::Info, $($arg)+));
     ($fmt:tt) => ($crate::log!($crate::Level::Info, expr))
 }
 
 #[macro_export]
 macro_rules! debug {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::log!(logger: $logger, $crate::LogLevel::Debug, $($arg)+));
     ($($arg:tt)+) => ($crate::log!($crate::LogLevel::Debug, $($arg)+));
     ($fmt:expr) => ($crate::log!($crate::Level::Debug, expr))
 }
 
 #[macro_export]
 macro_rules! trace {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::log!(logger: $logger, $crate::LogLevel::Trace, $($arg)+));
     ($($arg:tt)+) => ($crate::log!($crate::LogLevel::Trace, $($arg)+));
     ($fmt:expr) => ($crate::log!($crate::Level::Trace, expr))
 }
+
+#[macro_export]
+macro_rules! uerror {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::ulog!(logger: $logger, $crate::LogLevel::Error, $($arg)+));
+    ($($arg:tt)+) => ($crate::ulog!($crate::LogLevel::Error, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! uwarn {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::ulog!(logger: $logger, $crate::LogLevel::Warn, $($arg)+));
+    ($($arg:tt)+) => ($crate::ulog!($crate::LogLevel::Warn, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! uinfo {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::ulog!(logger: $logger, $crate::LogLevel::Info, $($arg)+));
+    ($($arg:tt)+) => ($crate::ulog!($crate::LogLevel::Info, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! udebug {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::ulog!(logger: $logger, $crate::LogLevel::Debug, $($arg)+));
+    ($($arg:tt)+) => ($crate::ulog!($crate::LogLevel::Debug, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! utrace {
+    (logger: $logger:expr, $($arg:tt)+) => ($crate::ulog!(logger: $logger, $crate::LogLevel::Trace, $($arg)+));
+    ($($arg:tt)+) => ($crate::ulog!($crate::LogLevel::Trace, $($arg)+));
+}
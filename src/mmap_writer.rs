@@ -0,0 +1,220 @@
+//! Writes a file through a shared memory mapping of a preallocated region, so appending a
+//! record is a `memcpy` instead of a `write(2)`.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+
+use crate::consts;
+
+/// Appends to `file` through a mapping of its first `capacity` bytes. The file is as long as
+/// the mapping while it is written, the zeros after the records are cut off by `finish`, or
+/// when the writer is dropped.
+pub(crate) struct MmapWriter {
+    file: File,
+    ptr: *mut u8,
+    capacity: usize,
+    len: usize,
+}
+
+// SAFETY: the mapping is owned by the writer and only accessed through `&mut self`
+unsafe impl Send for MmapWriter {}
+
+impl MmapWriter {
+    /// Maps a file that holds no records yet, allocating at least `min_capacity` bytes unless
+    /// the file is already that long, e.g. because the rotation helper preallocated it.
+    pub(crate) fn new(file: File, min_capacity: u64) -> io::Result<Self> {
+        let file_len = file.metadata()?.len();
+        let capacity = file_len.max(min_capacity).max(1);
+        Self::map(file, capacity, 0)
+    }
+
+    /// Maps an existing file to append to it, allocating at least `min_capacity` bytes like
+    /// `new`. A file of a process that crashed still has its preallocated zeros, records
+    /// continue after the last byte that is not zero.
+    pub(crate) fn append(file: File, min_capacity: u64) -> io::Result<Self> {
+        let file_len = file.metadata()?.len();
+        let capacity = file_len.max(min_capacity).max(1);
+        let mut writer = Self::map(file, capacity, 0)?;
+        // SAFETY: the first `file_len` bytes are mapped and were allocated by the file system
+        let content = unsafe { std::slice::from_raw_parts(writer.ptr, file_len as usize) };
+        writer.len = content.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+        Ok(writer)
+    }
+
+    fn map(file: File, capacity: u64, len: usize) -> io::Result<Self> {
+        let mut writer = MmapWriter {
+            file,
+            ptr: std::ptr::null_mut(),
+            capacity: 0,
+            len,
+        };
+        writer.remap(capacity)?;
+        Ok(writer)
+    }
+
+    /// Maps the first `capacity` bytes of the file, extending it if needed.
+    fn remap(&mut self, capacity: u64) -> io::Result<()> {
+        self.unmap();
+        preallocate(&self.file, capacity)?;
+        let capacity = usize::try_from(capacity)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: a fresh shared mapping of a file that is at least `capacity` bytes long
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                capacity,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        self.ptr = ptr as *mut u8;
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    /// Maps a larger region when `additional` bytes do not fit anymore, or the last attempt
+    /// to do so failed.
+    fn reserve(&mut self, additional: usize) -> io::Result<()> {
+        if !self.ptr.is_null() && self.len + additional <= self.capacity {
+            return Ok(());
+        }
+        // double small files, grow large ones by a constant step
+        let len = self.len as u64;
+        let grow = (additional as u64).max(consts::MMAP_GROW_BYTES.min(len.max(4096)));
+        self.remap(len + grow)
+    }
+
+    fn unmap(&mut self) {
+        if !self.ptr.is_null() {
+            // SAFETY: `ptr` and `capacity` describe the mapping created by `map`
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.capacity) };
+            self.ptr = std::ptr::null_mut();
+            self.capacity = 0;
+        }
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Bytes written so far, the file is cut to this length by `finish`
+    pub(crate) fn len(&self) -> u64 {
+        self.len as u64
+    }
+
+    /// Writes the mapped records to disk.
+    pub(crate) fn sync_data(&self) -> io::Result<()> {
+        if self.ptr.is_null() {
+            return self.file.sync_data();
+        }
+        // SAFETY: the written part of the mapping
+        let res = unsafe { libc::msync(self.ptr as *mut libc::c_void, self.len, libc::MS_SYNC) };
+        match res {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Unmaps the file and cuts it to the length of its records.
+    pub(crate) fn finish(self) -> io::Result<File> {
+        let mut this = std::mem::ManuallyDrop::new(self);
+        this.unmap();
+        // SAFETY: `this` is never used or dropped after the file is moved out
+        let file = unsafe { std::ptr::read(&this.file) };
+        file.set_len(this.len as u64)?;
+        Ok(file)
+    }
+}
+
+impl Write for MmapWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.reserve(buf.len())?;
+        // SAFETY: `reserve` made room for `buf` after the written part of the mapping
+        unsafe {
+            std::ptr::copy_nonoverlapping(buf.as_ptr(), self.ptr.add(self.len), buf.len());
+        }
+        self.len += buf.len();
+        Ok(buf.len())
+    }
+
+    /// The records are in the page cache as soon as they are copied, there is nothing to flush.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MmapWriter {
+    fn drop(&mut self) {
+        self.unmap();
+        let _ = self.file.set_len(self.len as u64);
+    }
+}
+
+/// Reserves `len` bytes for `file` and extends it to that length, so a full disk is reported
+/// here rather than as a SIGBUS on the mapping.
+pub(crate) fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    if file.metadata()?.len() >= len {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let len = libc::off_t::try_from(len)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: plain fallocate on an open file descriptor
+        match unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    file.set_len(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::fs::{self, OpenOptions};
+
+    #[test]
+    fn test_mmap_writer() {
+        let folder = TempDir::new("mmap");
+        let path = folder.join("test");
+        let open = || {
+            OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(true)
+                .open(&path)
+                .unwrap()
+        };
+
+        let mut writer = MmapWriter::new(open(), 8).unwrap();
+        writer.write_all(b"hello ").unwrap();
+        // grows past the preallocated 8 bytes
+        writer.write_all(b"world\n").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 6 + 4096);
+        writer.finish().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"hello world\n");
+
+        // a crashed writer leaves its preallocated zeros behind
+        open().set_len(64).unwrap();
+        let mut writer = MmapWriter::append(open(), 8).unwrap();
+        assert_eq!(writer.len(), 12);
+        writer.write_all(b"again\n").unwrap();
+        drop(writer);
+        assert_eq!(fs::read(&path).unwrap(), b"hello world\nagain\n");
+
+        // maps no more than asked for while the records fit
+        let writer = MmapWriter::append(open(), 100).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 100);
+        drop(writer);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 18);
+    }
+}
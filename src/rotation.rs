@@ -0,0 +1,680 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use symlink::{remove_symlink_auto, symlink_auto};
+
+use crate::compress::{self, Compression};
+use crate::file_name::{FileKey, FileNameTemplate};
+use crate::free_space;
+use crate::{NamedFile, WriterKind};
+
+type RotateFn = dyn Fn(&Path, &Path) + Send + Sync;
+
+/// Called with the closed and the new file after every rollover, see `Logger::on_rotate`
+#[derive(Clone)]
+pub(crate) struct RotateHook(Arc<RotateFn>);
+
+impl RotateHook {
+    pub(crate) fn new(f: impl Fn(&Path, &Path) + Send + Sync + 'static) -> Self {
+        RotateHook(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for RotateHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RotateHook")
+    }
+}
+
+/// A file the helper opened ahead of the next rollover under a name retention never matches
+pub(crate) struct PendingFile {
+    pub(crate) file: File,
+    pub(crate) path: PathBuf,
+}
+
+/// The backend started writing to `new_path`
+pub(crate) struct Opened {
+    /// The file written before, and whether it still needs a sync
+    pub(crate) closed: Option<(File, bool)>,
+    pub(crate) closed_file_path: Option<PathBuf>,
+    pub(crate) new_path: PathBuf,
+    /// Set if the backend writes to the pending file, which still has to be renamed
+    pub(crate) pending_path: Option<PathBuf>,
+}
+
+enum Job {
+    /// Starts placing the files of a logger
+    Register(Box<Worker>, Cleaner),
+    /// The logger was dropped, its pre-opened file is removed
+    Unregister,
+    Opened(Opened),
+    /// The backend dropped the pending file, e.g. because its folder was deleted
+    RenewPending,
+    /// Deletes the oldest files but `current` until the given number of bytes is free
+    FreeSpace {
+        min_free: u64,
+        current: PathBuf,
+    },
+    /// Acknowledged once the files of every job sent before are in place
+    Placed(Sender<()>),
+    /// Acknowledged once every job sent before is done, including the cleanup
+    Idle(Sender<()>),
+}
+
+/// Work left once the new file is in place, done by a thread of its own so that a slow
+/// compression or rotate hook never holds up the next rollover
+enum Cleanup {
+    Register(Cleaner),
+    Unregister,
+    Opened {
+        closed: Option<(File, bool)>,
+        closed_file_path: Option<PathBuf>,
+        new_path: PathBuf,
+    },
+    FreeSpace {
+        min_free: u64,
+        current: PathBuf,
+    },
+    Idle(Sender<()>),
+}
+
+/// Distinguishes the loggers of one process, on the threads and in pending file names
+static NEXT_HELPER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Two low priority threads doing the file system work around a rollover for every file of a
+/// backend, so the backend thread only swaps file handles. The first keeps the next file
+/// pre-opened, moves it into place and points the symlink at it. The second then syncs,
+/// compresses and closes the previous file, runs the rotate hook and applies retention.
+///
+/// The threads stop once every clone and every `RotationHelper` is dropped, after finishing
+/// the jobs handed over.
+#[derive(Clone)]
+pub(crate) struct RotationThreads(Arc<Threads>);
+
+struct Threads {
+    tx: Option<Sender<(u64, Job)>>,
+    /// The placing thread, then the cleanup thread
+    handles: Vec<thread::JoinHandle<()>>,
+}
+
+impl RotationThreads {
+    pub(crate) fn start() -> io::Result<Self> {
+        let (tx, rx) = unbounded::<(u64, Job)>();
+        let (cleanup_tx, cleanup_rx) = unbounded::<(u64, Cleanup)>();
+        let cleanup = thread::Builder::new()
+            .name("lll-cleanup".into())
+            .spawn(move || {
+                lower_thread_priority();
+                let mut cleaners = HashMap::new();
+                for (id, job) in cleanup_rx {
+                    match job {
+                        Cleanup::Register(cleaner) => {
+                            cleaners.insert(id, cleaner);
+                        }
+                        Cleanup::Unregister => {
+                            cleaners.remove(&id);
+                        }
+                        Cleanup::Idle(ack) => {
+                            let _ = ack.send(());
+                        }
+                        job => {
+                            if let Some(cleaner) = cleaners.get(&id) {
+                                cleaner.run(job);
+                            }
+                        }
+                    }
+                }
+            })?;
+        let rotation = thread::Builder::new()
+            .name("lll-rotation".into())
+            .spawn(move || {
+                lower_thread_priority();
+                let mut workers = HashMap::new();
+                for (id, job) in rx {
+                    match job {
+                        Job::Register(worker, cleaner) => {
+                            let _ = cleanup_tx.send((id, Cleanup::Register(cleaner)));
+                            let mut worker = *worker;
+                            worker.recover_pending();
+                            worker.prepare_pending();
+                            workers.insert(id, worker);
+                        }
+                        Job::Unregister => {
+                            if let Some(worker) = workers.remove(&id) {
+                                worker.discard_pending();
+                            }
+                            let _ = cleanup_tx.send((id, Cleanup::Unregister));
+                        }
+                        Job::Placed(ack) => {
+                            let _ = ack.send(());
+                        }
+                        Job::Idle(ack) => {
+                            let _ = cleanup_tx.send((id, Cleanup::Idle(ack)));
+                        }
+                        job => {
+                            let cleanup = workers.get_mut(&id).and_then(|w| w.run(job));
+                            if let Some(cleanup) = cleanup {
+                                let _ = cleanup_tx.send((id, cleanup));
+                            }
+                        }
+                    }
+                }
+            })?;
+        Ok(RotationThreads(Arc::new(Threads {
+            tx: Some(tx),
+            handles: vec![rotation, cleanup],
+        })))
+    }
+
+    /// Starts placing the files of `file` on these threads.
+    pub(crate) fn helper(
+        &self,
+        file: &NamedFile,
+        template: Arc<FileNameTemplate>,
+    ) -> RotationHelper {
+        let (pending_tx, pending_rx) = bounded::<PendingFile>(1);
+        let folder = PathBuf::from(&file.folder);
+        let id = NEXT_HELPER_ID.fetch_add(1, Ordering::Relaxed);
+        let worker = Worker {
+            folder: folder.clone(),
+            prefix: file.prefix.clone(),
+            id,
+            preallocate: match file.writer_kind {
+                WriterKind::Mmap => Some(file.preallocate()),
+                _ => None,
+            },
+            pending_tx,
+            pending_rx: pending_rx.clone(),
+            pending_taken: true,
+        };
+        let cleaner = Cleaner {
+            folder,
+            template,
+            max_files: file.max_files,
+            max_total_bytes: file.max_total_bytes,
+            max_age: file.max_age,
+            compression: file.compression,
+            on_rotate: file.on_rotate.clone(),
+        };
+        let helper = RotationHelper {
+            threads: self.clone(),
+            id,
+            pending_rx,
+        };
+        helper.send(Job::Register(Box::new(worker), cleaner));
+        helper
+    }
+
+    fn send(&self, id: u64, job: Job) -> bool {
+        self.0
+            .tx
+            .as_ref()
+            .is_some_and(|tx| tx.send((id, job)).is_ok())
+    }
+}
+
+impl Drop for Threads {
+    fn drop(&mut self) {
+        self.tx.take();
+        // the cleanup thread stops once the placing thread dropped its sender
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The jobs of one logger on its `RotationThreads`. Dropping it removes the pre-opened file.
+pub(crate) struct RotationHelper {
+    threads: RotationThreads,
+    id: u64,
+    pending_rx: Receiver<PendingFile>,
+}
+
+impl RotationHelper {
+    /// The threads this helper runs on, e.g. for the helper of a fallback file
+    pub(crate) fn threads(&self) -> &RotationThreads {
+        &self.threads
+    }
+
+    fn send(&self, job: Job) -> bool {
+        self.threads.send(self.id, job)
+    }
+
+    /// The pre-opened file, if the helper got to it since the last rollover.
+    pub(crate) fn take_pending(&self) -> Option<PendingFile> {
+        self.pending_rx.try_recv().ok()
+    }
+
+    pub(crate) fn opened(&self, opened: Opened) {
+        self.send(Job::Opened(opened));
+    }
+
+    /// Removes the pre-opened file if the backend did not take it.
+    pub(crate) fn discard_pending(&self) {
+        discard_pending(&self.pending_rx);
+    }
+
+    /// Replaces the pre-opened file with a new one. Call it once the files are in place, see
+    /// `wait_placed`.
+    pub(crate) fn renew_pending(&self) {
+        self.discard_pending();
+        self.send(Job::RenewPending);
+    }
+
+    /// Deletes the oldest files but `current` until `min_free` bytes are available.
+    pub(crate) fn free_space(&self, min_free: u64, current: PathBuf) {
+        self.send(Job::FreeSpace { min_free, current });
+    }
+
+    /// Blocks until the files handed over so far are moved into place and the next one is
+    /// pre-opened, without waiting for compression, the rotate hook or retention.
+    pub(crate) fn wait_placed(&self) {
+        self.wait(Job::Placed);
+    }
+
+    /// Blocks until the jobs handed over so far are done.
+    pub(crate) fn wait_idle(&self) {
+        self.wait(Job::Idle);
+    }
+
+    fn wait(&self, barrier: impl FnOnce(Sender<()>) -> Job) {
+        let (ack_tx, ack_rx) = bounded(1);
+        if self.send(barrier(ack_tx)) {
+            let _ = ack_rx.recv();
+        }
+    }
+}
+
+impl Drop for RotationHelper {
+    fn drop(&mut self) {
+        // the placing thread removes a file it pre-opens after this
+        self.discard_pending();
+        self.send(Job::Unregister);
+    }
+}
+
+fn discard_pending(pending_rx: &Receiver<PendingFile>) {
+    if let Ok(pending) = pending_rx.try_recv() {
+        drop(pending.file);
+        let _ = fs::remove_file(&pending.path);
+    }
+}
+
+/// State of the thread that places files
+struct Worker {
+    folder: PathBuf,
+    prefix: String,
+    /// Part of the pending file name, see `pending_path`
+    id: u64,
+    /// Size the pending file is allocated with for the mmap writer
+    #[cfg_attr(not(unix), allow(dead_code))]
+    preallocate: Option<u64>,
+    pending_tx: Sender<PendingFile>,
+    /// The other end of `pending_tx`, to remove the file once the logger is gone
+    pending_rx: Receiver<PendingFile>,
+    /// Whether the backend took the last pending file, so a new one is needed
+    pending_taken: bool,
+}
+
+impl Worker {
+    fn run(&mut self, job: Job) -> Option<Cleanup> {
+        match job {
+            Job::Opened(opened) => return Some(self.opened(opened)),
+            Job::RenewPending => {
+                self.pending_taken = true;
+                self.prepare_pending();
+            }
+            Job::FreeSpace { min_free, current } => {
+                return Some(Cleanup::FreeSpace { min_free, current })
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn discard_pending(&self) {
+        discard_pending(&self.pending_rx);
+    }
+
+    fn opened(&mut self, opened: Opened) -> Cleanup {
+        let Opened {
+            closed,
+            closed_file_path,
+            new_path,
+            pending_path,
+        } = opened;
+        if let Some(pending_path) = pending_path {
+            self.pending_taken = true;
+            if let Err(e) = fs::rename(&pending_path, &new_path) {
+                eprintln!(
+                    "WARNING: Failed to move logfile into place {}: {}",
+                    new_path.to_string_lossy(),
+                    e
+                );
+            }
+        }
+        self.update_symlink(&new_path);
+        // ready before the closed file is compressed, so back-to-back rollovers find it
+        self.prepare_pending();
+        Cleanup::Opened {
+            closed,
+            closed_file_path,
+            new_path,
+        }
+    }
+
+    /// `.{prefix}.next.{pid}.{id}`, which no other logger uses, not even one in another process
+    /// sharing the folder and prefix
+    fn pending_path(&self) -> PathBuf {
+        self.folder.join(format!(
+            ".{}.next.{}.{}",
+            self.prefix,
+            std::process::id(),
+            self.id
+        ))
+    }
+
+    /// Keeps what crashed processes wrote to their pending files, and removes the files, which
+    /// would be left behind otherwise. Files of processes that are still running are theirs.
+    /// A preallocated file starts with a zero until records are written to it. Only unix can
+    /// tell whether a process runs, elsewhere the files of crashed processes stay until they
+    /// are removed by hand.
+    fn recover_pending(&self) {
+        let Ok(files) = fs::read_dir(&self.folder) else {
+            return;
+        };
+        let stem = format!(".{}.next.", self.prefix);
+        for f in files.flatten() {
+            let fname = f.file_name();
+            let pid = fname
+                .to_str()
+                .and_then(|fname| fname.strip_prefix(&stem))
+                .and_then(|rest| rest.split_once('.'))
+                .filter(|(_, id)| id.parse::<u64>().is_ok())
+                .and_then(|(pid, _)| pid.parse::<u32>().ok());
+            let Some(pid) = pid else {
+                continue;
+            };
+            if pid == std::process::id() || process_alive(pid) {
+                continue;
+            }
+            let path = f.path();
+            let mut first = [0u8];
+            let written = File::open(&path)
+                .and_then(|mut f| f.read(&mut first))
+                .is_ok_and(|n| n == 1 && first[0] != 0);
+            if !written {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            let secs = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let mut recovered = path.clone().into_os_string();
+            recovered.push(format!(".recovered.{}", secs));
+            eprintln!(
+                "WARNING: Found records of a previous run in {}, kept as {}",
+                path.to_string_lossy(),
+                recovered.to_string_lossy()
+            );
+            let _ = fs::rename(&path, recovered);
+        }
+    }
+
+    fn prepare_pending(&mut self) {
+        if !self.pending_taken {
+            return;
+        }
+        let path = self.pending_path();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path);
+        match file {
+            Ok(file) => {
+                #[cfg(unix)]
+                if let Some(len) = self.preallocate {
+                    // the backend maps what is there, or allocates it itself if this fails
+                    if let Err(e) = crate::mmap_writer::preallocate(&file, len) {
+                        eprintln!(
+                            "WARNING: Failed to preallocate logfile {}: {}",
+                            path.to_string_lossy(),
+                            e
+                        );
+                    }
+                }
+                self.pending_taken = false;
+                let _ = self.pending_tx.try_send(PendingFile { file, path });
+            }
+            // the backend opens the next file itself until this works again
+            Err(e) => eprintln!(
+                "WARNING: Failed to pre-open logfile {}: {}",
+                path.to_string_lossy(),
+                e
+            ),
+        }
+    }
+
+    /// Makes a soft link named `prefix` to the latest file, unless the file itself is named so
+    fn update_symlink(&self, new_path: &Path) {
+        if new_path.file_name() == Some(self.prefix.as_ref()) {
+            return;
+        }
+        if let (Ok(folder), Ok(target)) = (self.folder.canonicalize(), new_path.canonicalize()) {
+            let _ = remove_symlink_auto(self.folder.join(&self.prefix));
+            let _ = symlink_auto(target, folder.join(&self.prefix));
+        }
+    }
+}
+
+/// State of the cleanup thread
+struct Cleaner {
+    folder: PathBuf,
+    template: Arc<FileNameTemplate>,
+    max_files: usize,
+    max_total_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    compression: Compression,
+    on_rotate: Option<RotateHook>,
+}
+
+impl Cleaner {
+    fn run(&self, job: Cleanup) {
+        match job {
+            Cleanup::Opened {
+                closed,
+                closed_file_path,
+                new_path,
+            } => self.opened(closed, closed_file_path, &new_path),
+            Cleanup::FreeSpace { min_free, current } => self.free_space(min_free, &current),
+            _ => {}
+        }
+    }
+
+    fn opened(
+        &self,
+        closed: Option<(File, bool)>,
+        closed_file_path: Option<PathBuf>,
+        new_path: &Path,
+    ) {
+        if let Some((file, need_sync)) = closed {
+            if need_sync {
+                if let Err(e) = file.sync_data() {
+                    eprintln!("WARNING: Failed to sync closed logfile: {}", e);
+                }
+            }
+        }
+        if let Some(closed_file_path) = closed_file_path {
+            self.rotated(closed_file_path, new_path);
+        }
+        if let Err(e) = self.remove_expired(new_path) {
+            eprintln!(
+                "WARNING: Failed to apply retention in {}: {}",
+                self.folder.to_string_lossy(),
+                e
+            );
+        }
+    }
+
+    fn rotated(&self, mut closed_path: PathBuf, new_path: &Path) {
+        match compress::compress_file(self.compression, &closed_path) {
+            Ok(compressed_path) => closed_path = compressed_path,
+            Err(e) => eprintln!(
+                "WARNING: Failed to compress logfile {}: {}",
+                closed_path.to_string_lossy(),
+                e
+            ),
+        }
+        if let Some(RotateHook(on_rotate)) = &self.on_rotate {
+            let res = panic::catch_unwind(AssertUnwindSafe(|| on_rotate(&closed_path, new_path)));
+            if res.is_err() {
+                eprintln!(
+                    "WARNING: Rotate hook panicked for logfile {}",
+                    closed_path.to_string_lossy()
+                );
+            }
+        }
+    }
+
+    /// Regular files whose whole name parses as one of ours with their size and modification
+    /// time, newest first.
+    fn log_files(&self) -> io::Result<Vec<LogFile>> {
+        let files = fs::read_dir(&self.folder)?;
+        let mut log_files = vec![];
+        for f in files.flatten() {
+            if !f.file_type().is_ok_and(|t| t.is_file()) {
+                continue;
+            }
+            let Ok(fname) = f.file_name().into_string() else {
+                continue;
+            };
+            let Some(key) = self
+                .template
+                .parse(self.compression.strip_extension(&fname))
+            else {
+                continue;
+            };
+            let (size, modified) = match f.metadata() {
+                Ok(m) => (m.len(), m.modified().ok()),
+                Err(_) => (0, None),
+            };
+            log_files.push((key, fname, size, modified));
+        }
+
+        log_files.sort_by(|(a_key, a, ..), (b_key, b, ..)| {
+            b_key.cmp(a_key).then(crate::natural_cmp(b, a))
+        });
+        Ok(log_files)
+    }
+
+    fn remove_expired(&self, current_path: &Path) -> io::Result<()> {
+        let log_files = self.log_files()?;
+        let now = SystemTime::now();
+        let mut total_bytes = 0u64;
+        for (i, (_, f, size, modified)) in log_files.into_iter().enumerate() {
+            let p = self.folder.join(f);
+            total_bytes = total_bytes.saturating_add(size);
+            let too_old = match (self.max_age, modified) {
+                (Some(max_age), Some(modified)) => {
+                    now.duration_since(modified).is_ok_and(|age| age > max_age)
+                }
+                _ => false,
+            };
+            let expired = i >= self.max_files
+                || self.max_total_bytes.is_some_and(|max| total_bytes > max)
+                || too_old;
+            if !expired || p == current_path {
+                continue;
+            }
+            if let Err(e) = fs::remove_file(&p) {
+                eprintln!(
+                    "WARNING: Failed to remove old logfile {}: {}",
+                    p.to_string_lossy(),
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn free_space(&self, min_free: u64, current_path: &Path) {
+        let enough_free =
+            || free_space::available_space(&self.folder).map_or(true, |free| free >= min_free);
+        let log_files = match self.log_files() {
+            Ok(log_files) => log_files,
+            Err(e) => {
+                return eprintln!(
+                    "WARNING: Failed to free space in {}: {}",
+                    self.folder.to_string_lossy(),
+                    e
+                )
+            }
+        };
+        let mut removed = 0;
+        for (_, f, ..) in log_files.into_iter().rev() {
+            if enough_free() {
+                break;
+            }
+            let p = self.folder.join(f);
+            if p == current_path {
+                continue;
+            }
+            match fs::remove_file(&p) {
+                Ok(()) => removed += 1,
+                Err(e) => eprintln!(
+                    "WARNING: Failed to remove old logfile {}: {}",
+                    p.to_string_lossy(),
+                    e
+                ),
+            }
+        }
+        if removed > 0 {
+            eprintln!(
+                "WARNING: Removed {} old logfiles in {} to free space",
+                removed,
+                self.folder.to_string_lossy()
+            );
+        }
+    }
+}
+
+/// A file found by retention: its parsed name, name, size and modification time
+type LogFile = (FileKey, String, u64, Option<SystemTime>);
+
+fn lower_thread_priority() {
+    // on Linux the nice value is per thread, `who == 0` is the calling thread
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::setpriority(libc::PRIO_PROCESS, 0, 19);
+    }
+}
+
+/// Whether the process `pid` is running. Without a way to tell, e.g. on Windows, every process
+/// counts as running: removing the pending file of a live one would lose its records.
+fn process_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+        // SAFETY: signal 0 only checks whether the process exists
+        let exists = unsafe { libc::kill(pid, 0) } == 0;
+        exists || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        true
+    }
+}
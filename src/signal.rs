@@ -0,0 +1,314 @@
+//! Signal handlers of the logger, they only touch atomics and leave the work to the backend.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
+
+static SIGHUP_COUNT: AtomicU64 = AtomicU64::new(0);
+/// The fatal signal of the last crash, and how many crashes there were, simulated ones
+/// included
+static CRASH_SIGNAL: AtomicI32 = AtomicI32::new(0);
+static CRASH_COUNT: AtomicU64 = AtomicU64::new(0);
+/// Backends that drain their queue on a fatal signal, and how many of them are done with the
+/// last crash
+static CRASH_WATCHERS: AtomicUsize = AtomicUsize::new(0);
+static CRASH_DRAINED: AtomicUsize = AtomicUsize::new(0);
+
+/// How many SIGHUPs arrived since `watch_sighup` installed the handler
+pub(crate) fn sighup_count() -> u64 {
+    SIGHUP_COUNT.load(Ordering::Relaxed)
+}
+
+/// Counts SIGHUPs from now on, calling the handler installed before, and returns the current
+/// count.
+#[cfg(unix)]
+pub(crate) fn watch_sighup() -> io::Result<u64> {
+    use std::sync::{Mutex, OnceLock};
+
+    static PREVIOUS: OnceLock<libc::sigaction> = OnceLock::new();
+    static INSTALLED: Mutex<bool> = Mutex::new(false);
+
+    extern "C" fn on_sighup(
+        signum: libc::c_int,
+        info: *mut libc::siginfo_t,
+        context: *mut libc::c_void,
+    ) {
+        SIGHUP_COUNT.fetch_add(1, Ordering::Relaxed);
+        if let Some(previous) = PREVIOUS.get() {
+            // SAFETY: `previous` is the action the kernel would have run for this signal
+            unsafe { chain(previous, signum, info, context) };
+        }
+    }
+
+    let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+    if !*installed {
+        // SAFETY: plain sigaction calls with valid pointers, `previous` is stored before our
+        // handler can run so it never misses the handler it chains to
+        unsafe {
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGHUP, std::ptr::null(), &mut previous) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let _ = PREVIOUS.set(previous);
+
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sighup as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(libc::SIGHUP, &action, std::ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        *installed = true;
+    }
+    Ok(sighup_count())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn watch_sighup() -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SIGHUP is only available on unix",
+    ))
+}
+
+/// Registration of a backend that drains its queue when the process receives a fatal signal,
+/// see `watch_fatal_signals`
+pub(crate) struct CrashWatch {
+    /// The number of the last crash this backend drained its queue for
+    handled: u64,
+}
+
+impl CrashWatch {
+    #[cfg(unix)]
+    fn new() -> Self {
+        CRASH_WATCHERS.fetch_add(1, Ordering::AcqRel);
+        CrashWatch {
+            handled: CRASH_COUNT.load(Ordering::Acquire),
+        }
+    }
+
+    /// The fatal signal of a crash this backend did not drain its queue for yet
+    pub(crate) fn take_signal(&mut self) -> Option<i32> {
+        let crash = CRASH_COUNT.load(Ordering::Acquire);
+        if crash == self.handled {
+            return None;
+        }
+        self.handled = crash;
+        Some(CRASH_SIGNAL.load(Ordering::Acquire))
+    }
+
+    /// Lets the signal handler go on once every backend drained its queue, unless another
+    /// crash began meanwhile.
+    pub(crate) fn drained(&self) {
+        if CRASH_COUNT.load(Ordering::Acquire) == self.handled {
+            CRASH_DRAINED.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+#[cfg(unix)]
+/// Makes every watching backend drain its queue, counting from zero again.
+fn begin_crash(signum: i32) {
+    CRASH_DRAINED.store(0, Ordering::Release);
+    CRASH_SIGNAL.store(signum, Ordering::Release);
+    CRASH_COUNT.fetch_add(1, Ordering::AcqRel);
+}
+
+impl Drop for CrashWatch {
+    fn drop(&mut self) {
+        CRASH_WATCHERS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(unix)]
+const FATAL_SIGNALS: [libc::c_int; 3] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGABRT];
+
+/// Handles SIGSEGV, SIGBUS and SIGABRT from now on: the handler writes a crash marker to
+/// stderr, waits at most `consts::CRASH_DRAIN_TIMEOUT` for every watching backend to drain its
+/// queue and re-raises the signal with the action installed before.
+#[cfg(unix)]
+pub(crate) fn watch_fatal_signals() -> io::Result<CrashWatch> {
+    use std::sync::{Mutex, OnceLock};
+
+    static PREVIOUS: OnceLock<[libc::sigaction; 3]> = OnceLock::new();
+    static INSTALLED: Mutex<bool> = Mutex::new(false);
+    static CRASHING: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_fatal_signal(
+        signum: libc::c_int,
+        info: *mut libc::siginfo_t,
+        _context: *mut libc::c_void,
+    ) {
+        if !CRASHING.swap(true, Ordering::AcqRel) {
+            write_crash_marker(signum);
+            begin_crash(signum);
+        }
+        // a second thread crashing meanwhile waits as well instead of ending the process early
+        wait_for_drain(crate::consts::CRASH_DRAIN_TIMEOUT);
+
+        let index = FATAL_SIGNALS.iter().position(|s| *s == signum);
+        let previous = PREVIOUS.get().zip(index).map(|(p, i)| &p[i]);
+        // SAFETY: async-signal-safe calls, `previous` is the action the kernel would have run
+        unsafe {
+            match previous {
+                Some(previous) => {
+                    libc::sigaction(signum, previous, std::ptr::null_mut());
+                }
+                None => {
+                    libc::signal(signum, libc::SIG_DFL);
+                }
+            }
+            // a fault the kernel raised happens again once the handler returns, with its
+            // original details for the previous handler
+            if info.is_null() || (*info).si_code <= 0 {
+                libc::raise(signum);
+            }
+        }
+    }
+
+    let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+    if !*installed {
+        // SAFETY: plain sigaction calls with valid pointers, `previous` is stored before our
+        // handler can run
+        unsafe {
+            let mut previous: [libc::sigaction; 3] = std::mem::zeroed();
+            for (signum, previous) in FATAL_SIGNALS.iter().zip(&mut previous) {
+                if libc::sigaction(*signum, std::ptr::null(), previous) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            let _ = PREVIOUS.set(previous);
+
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_fatal_signal as *const () as libc::sighandler_t;
+            // runs on the alternate stack std sets up, so a stack overflow is handled as well
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            for signum in FATAL_SIGNALS {
+                if libc::sigaction(signum, &action, std::ptr::null_mut()) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        *installed = true;
+    }
+    Ok(CrashWatch::new())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn watch_fatal_signals() -> io::Result<CrashWatch> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "fatal signals are only handled on unix",
+    ))
+}
+
+/// Writes `FATAL: Received signal <signum>, flushing logs` to stderr without allocating.
+#[cfg(unix)]
+fn write_crash_marker(signum: libc::c_int) {
+    const PREFIX: &[u8] = b"FATAL: Received signal ";
+    const SUFFIX: &[u8] = b", flushing logs\n";
+    let mut buf = [0u8; 64];
+    buf[..PREFIX.len()].copy_from_slice(PREFIX);
+    let mut len = PREFIX.len();
+    let mut digits = [0u8; 10];
+    let mut n = signum.unsigned_abs();
+    let mut i = digits.len();
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    buf[len..len + digits.len() - i].copy_from_slice(&digits[i..]);
+    len += digits.len() - i;
+    buf[len..len + SUFFIX.len()].copy_from_slice(SUFFIX);
+    len += SUFFIX.len();
+    // SAFETY: write(2) is async-signal-safe and `buf` holds `len` bytes
+    unsafe {
+        libc::write(
+            libc::STDERR_FILENO,
+            buf.as_ptr() as *const libc::c_void,
+            len,
+        )
+    };
+}
+
+/// Waits until every watching backend drained its queue, or `timeout` expired. Only uses
+/// async-signal-safe calls.
+#[cfg(unix)]
+fn wait_for_drain(timeout: std::time::Duration) -> bool {
+    fn monotonic_ns() -> u64 {
+        // SAFETY: clock_gettime(2) is async-signal-safe and fills in `ts`
+        unsafe {
+            let mut ts: libc::timespec = std::mem::zeroed();
+            libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+            ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+        }
+    }
+
+    let deadline = monotonic_ns() + timeout.as_nanos() as u64;
+    loop {
+        if CRASH_DRAINED.load(Ordering::Acquire) >= CRASH_WATCHERS.load(Ordering::Acquire) {
+            return true;
+        }
+        if monotonic_ns() >= deadline {
+            return false;
+        }
+        let step = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 1_000_000,
+        };
+        // SAFETY: nanosleep(2) is async-signal-safe
+        unsafe { libc::nanosleep(&step, std::ptr::null_mut()) };
+    }
+}
+
+/// Pretends the process received `signum`, returning whether every watching backend drained
+/// its queue in time.
+#[cfg(all(test, unix))]
+pub(crate) fn simulate_crash(signum: libc::c_int) -> bool {
+    begin_crash(signum);
+    wait_for_drain(std::time::Duration::from_secs(5))
+}
+
+/// Runs a handler that was installed before ours, unless it is the default or ignore action.
+///
+/// # Safety
+///
+/// Must only be called from a signal handler with the arguments it received.
+#[cfg(unix)]
+unsafe fn chain(
+    previous: &libc::sigaction,
+    signum: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    let handler = previous.sa_sigaction;
+    if handler == libc::SIG_DFL || handler == libc::SIG_IGN {
+        return;
+    }
+    if previous.sa_flags & libc::SA_SIGINFO != 0 {
+        let f: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+            std::mem::transmute(handler);
+        f(signum, info, context);
+    } else {
+        let f: extern "C" fn(libc::c_int) = std::mem::transmute(handler);
+        f(signum);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sighup_is_counted() {
+        let before = watch_sighup().unwrap();
+        // SAFETY: our handler is installed, so SIGHUP does not terminate the test
+        unsafe { libc::raise(libc::SIGHUP) };
+        assert!(sighup_count() > before);
+    }
+}
@@ -0,0 +1,83 @@
+//! Fixtures shared by the tests of every module
+
+use std::path::{Path, PathBuf};
+
+/// An empty folder named after the test and the process, removed again when dropped so a
+/// failing test does not leave it behind
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = temp_dir_path(name, std::process::id());
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    /// The path as the `String` the logger builders take
+    pub(crate) fn to_folder(&self) -> String {
+        self.0.to_string_lossy().to_string()
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn temp_dir_path(name: &str, pid: u32) -> PathBuf {
+    std::env::temp_dir().join(format!("lll_{}_{}", name, pid))
+}
+
+/// The folder `TempDir::new(name)` created in the process that started this child, for a child
+/// that crashes and can not remove its own
+#[cfg(unix)]
+pub(crate) fn parent_temp_dir(name: &str) -> PathBuf {
+    temp_dir_path(name, std::os::unix::process::parent_id())
+}
+
+const CHILD_ENV: &str = "LLL_TEST_CHILD";
+
+/// Whether this process was started by `run_in_child`
+pub(crate) fn in_child() -> bool {
+    std::env::var_os(CHILD_ENV).is_some()
+}
+
+/// Runs the test `name` alone in a new process of this test binary, for tests that change
+/// process wide state such as the global logger or that crash on purpose. The test checks
+/// `in_child` to tell which side it is on.
+pub(crate) fn run_in_child(name: &str) -> std::process::Output {
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args([name, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    // a misspelled name runs no test and succeeds
+    assert!(String::from_utf8_lossy(&output.stdout).contains("running 1 test"));
+    output
+}
+
+/// Fails with the output of the child if it did not exit successfully
+pub(crate) fn assert_child_succeeded(output: &std::process::Output) {
+    assert!(
+        output.status.success(),
+        "child failed with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
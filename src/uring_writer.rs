@@ -0,0 +1,266 @@
+//! Writes a file through io_uring with two buffers, so the backend thread formats records into
+//! one buffer while the kernel writes the other, without a thread of our own.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem::ManuallyDrop;
+use std::os::unix::io::AsRawFd;
+
+use io_uring::{opcode, types, IoUring};
+
+thread_local! {
+    /// Rings of closed writers. The next file opened on the same backend thread takes one
+    /// instead of setting up a ring at every rollover.
+    static SPARE_RINGS: RefCell<Vec<IoUring>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A buffer handed to the kernel, kept after a failed write until it is submitted again
+struct InFlight {
+    written: usize,
+    submitted: bool,
+}
+
+/// Appends to `file` with io_uring writes of whole buffers. `active` is the buffer records are
+/// formatted into, the other one belongs to the kernel while `in_flight` is set.
+pub(crate) struct UringWriter {
+    /// Handed back to `SPARE_RINGS` once nothing is in flight anymore
+    ring: ManuallyDrop<IoUring>,
+    file: File,
+    buffers: [Vec<u8>; 2],
+    active: usize,
+    in_flight: Option<InFlight>,
+    /// File offset the next byte the kernel writes goes to
+    offset: u64,
+}
+
+impl UringWriter {
+    pub(crate) fn new(file: File, capacity: usize) -> io::Result<Self> {
+        let ring = match SPARE_RINGS.with_borrow_mut(Vec::pop) {
+            Some(ring) => ring,
+            None => IoUring::new(4)?,
+        };
+        Ok(UringWriter {
+            ring: ManuallyDrop::new(ring),
+            offset: file.metadata()?.len(),
+            file,
+            buffers: [Vec::with_capacity(capacity), Vec::with_capacity(capacity)],
+            active: 0,
+            in_flight: None,
+        })
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Length of the file once every buffered record is written
+    pub(crate) fn len(&self) -> u64 {
+        let in_flight = self
+            .in_flight
+            .as_ref()
+            .map_or(0, |f| self.buffers[self.active ^ 1].len() - f.written);
+        self.offset + in_flight as u64 + self.buffers[self.active].len() as u64
+    }
+
+    /// Hands the formatted records to the kernel without waiting for them to be written. When
+    /// the kernel still writes the previous buffer, records keep collecting until the next call.
+    pub(crate) fn submit(&mut self) -> io::Result<()> {
+        self.complete(false)?;
+        match &self.in_flight {
+            None => self.swap_buffers(),
+            Some(f) if !f.submitted => self.submit_in_flight(),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Waits until the kernel wrote what was submitted and syncs it to disk.
+    pub(crate) fn sync_data(&mut self) -> io::Result<()> {
+        self.wait()?;
+        self.file.sync_data()
+    }
+
+    /// Flushes and closes the writer, returning the file.
+    pub(crate) fn finish(mut self) -> io::Result<File> {
+        self.flush()?;
+        let this = std::mem::ManuallyDrop::new(self);
+        // SAFETY: nothing is in flight, `this` is never used or dropped after its fields are
+        // moved out
+        unsafe {
+            let ring = ManuallyDrop::into_inner(std::ptr::read(&this.ring));
+            SPARE_RINGS.with_borrow_mut(|rings| rings.push(ring));
+            drop(std::ptr::read(&this.buffers));
+            Ok(std::ptr::read(&this.file))
+        }
+    }
+
+    /// Closes the writer without flushing, returning the records the kernel did not write.
+    pub(crate) fn into_unwritten(mut self) -> Vec<u8> {
+        let _ = self.wait();
+        let mut unwritten = Vec::new();
+        if let Some(f) = self.in_flight.take_if(|f| !f.submitted) {
+            unwritten.extend_from_slice(&self.buffers[self.active ^ 1][f.written..]);
+        }
+        unwritten.append(&mut self.buffers[self.active]);
+        unwritten
+    }
+
+    /// Makes the full buffer the one in flight and submits it.
+    fn swap_buffers(&mut self) -> io::Result<()> {
+        debug_assert!(self.in_flight.is_none());
+        if self.buffers[self.active].is_empty() {
+            return Ok(());
+        }
+        self.active ^= 1;
+        self.in_flight = Some(InFlight {
+            written: 0,
+            submitted: false,
+        });
+        self.submit_in_flight()
+    }
+
+    /// Submits the part of the buffer in flight the kernel did not write yet.
+    fn submit_in_flight(&mut self) -> io::Result<()> {
+        let Some(f) = &mut self.in_flight else {
+            return Ok(());
+        };
+        let rest = &self.buffers[self.active ^ 1][f.written..];
+        let len = u32::try_from(rest.len()).unwrap_or(u32::MAX);
+        let entry = opcode::Write::new(types::Fd(self.file.as_raw_fd()), rest.as_ptr(), len)
+            .offset(self.offset)
+            .build();
+        // SAFETY: the buffer is neither touched nor freed until its completion is reaped
+        unsafe { self.ring.submission().push(&entry) }
+            .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+        self.ring.submit()?;
+        f.submitted = true;
+        Ok(())
+    }
+
+    /// Reaps the completion of the buffer in flight, waiting for it if `wait` is set. Short
+    /// writes are submitted again, a failed write stays in flight unsubmitted.
+    fn complete(&mut self, wait: bool) -> io::Result<()> {
+        while self.in_flight.as_ref().is_some_and(|f| f.submitted) {
+            if wait {
+                match self.ring.submit_and_wait(1) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    res => res?,
+                };
+            }
+            let Some(cqe) = self.ring.completion().next() else {
+                match wait {
+                    true => continue,
+                    false => return Ok(()),
+                }
+            };
+            let f = self.in_flight.as_mut().unwrap();
+            f.submitted = false;
+            let written = match cqe.result() {
+                res if res < 0 => return Err(io::Error::from_raw_os_error(-res)),
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                res => res as usize,
+            };
+            f.written += written;
+            self.offset += written as u64;
+            if f.written == self.buffers[self.active ^ 1].len() {
+                self.buffers[self.active ^ 1].clear();
+                self.in_flight = None;
+            } else {
+                self.submit_in_flight()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until the buffer in flight is written, submitting it again if it failed before.
+    fn wait(&mut self) -> io::Result<()> {
+        while let Some(f) = &self.in_flight {
+            if !f.submitted {
+                self.submit_in_flight()?;
+            }
+            self.complete(true)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for UringWriter {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let active = &self.buffers[self.active];
+        if !active.is_empty() && active.len() + buf.len() > active.capacity() {
+            self.wait()?;
+            self.swap_buffers()?;
+        }
+        self.buffers[self.active].extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Writes every buffered record to the file, waiting for the kernel.
+    fn flush(&mut self) -> io::Result<()> {
+        self.wait()?;
+        self.swap_buffers()?;
+        self.wait()
+    }
+}
+
+impl Drop for UringWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+        // SAFETY: the ring is not used after this
+        let ring = unsafe { ManuallyDrop::take(&mut self.ring) };
+        if self.in_flight.as_ref().is_some_and(|f| f.submitted) {
+            // waiting for the kernel failed, leak the buffer rather than free it under a write,
+            // and close the ring so the next writer never reaps that write
+            std::mem::forget(std::mem::take(&mut self.buffers[self.active ^ 1]));
+            return;
+        }
+        SPARE_RINGS.with_borrow_mut(|rings| rings.push(ring));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::fs::{self, OpenOptions};
+
+    #[test]
+    fn test_uring_writer() {
+        let folder = TempDir::new("uring");
+        let path = folder.join("test");
+        fs::write(&path, b"old\n").unwrap();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&path)
+            .unwrap();
+
+        let mut writer = UringWriter::new(file, 8).unwrap();
+        writer.write_all(b"hello ").unwrap();
+        // fills the first buffer, it is written while the second one collects records
+        writer.write_all(b"world\n").unwrap();
+        writer.sync_data().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"old\nhello ");
+        writer.submit().unwrap();
+        writer.write_all(b"again\n").unwrap();
+        assert_eq!(writer.len(), 22);
+        writer.sync_data().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"old\nhello world\n");
+        writer.finish().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"old\nhello world\nagain\n");
+        assert_eq!(SPARE_RINGS.with_borrow(Vec::len), 1);
+
+        // the next file on this thread takes over the ring
+        let file = OpenOptions::new().append(true).open(&path).unwrap();
+        let mut writer = UringWriter::new(file, 8).unwrap();
+        assert_eq!(SPARE_RINGS.with_borrow(Vec::len), 0);
+        writer.write_all(b"reused\n").unwrap();
+        drop(writer);
+        assert_eq!(SPARE_RINGS.with_borrow(Vec::len), 1);
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            b"old\nhello world\nagain\nreused\n"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+#[cfg(unix)]
+use crate::mmap_writer::MmapWriter;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring_writer::UringWriter;
+
+const BUFFER_CAPACITY: usize = 1024 * 1024;
+
+/// How records are written to a file, see `Logger::writer`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum WriterKind {
+    /// Buffers records in memory and writes them with `write(2)` when flushed
+    #[default]
+    Buffered,
+    /// Copies records into a shared memory mapping of the file, so writing makes no system
+    /// calls. Files are preallocated to the `max_size` of the rolling condition and cut to the
+    /// length of their records when closed. Size based rollover happens before a record that
+    /// would not fit anymore. Only available on unix.
+    Mmap,
+    /// Like `Buffered`, but flushes submit the buffer to io_uring and return, the backend
+    /// thread formats records into a second buffer while the kernel writes the first one.
+    /// Falls back to `Buffered` when the kernel refuses io_uring.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    IoUring,
+}
+
+/// The file a `RollingLogger` writes to
+pub(crate) enum LogFileWriter {
+    Buffered(BufWriter<File>),
+    #[cfg(unix)]
+    Mmap(MmapWriter),
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    IoUring(Box<UringWriter>),
+}
+
+impl LogFileWriter {
+    /// Starts writing to a file that holds no records yet, `preallocate` is the size the mmap
+    /// writer maps up front.
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub(crate) fn new(kind: WriterKind, file: File, preallocate: u64) -> io::Result<Self> {
+        match kind {
+            WriterKind::Buffered => Ok(Self::buffered(file)),
+            #[cfg(unix)]
+            WriterKind::Mmap => MmapWriter::new(file, preallocate).map(LogFileWriter::Mmap),
+            #[cfg(not(unix))]
+            WriterKind::Mmap => Err(Self::unsupported()),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            WriterKind::IoUring => Self::uring(file),
+        }
+    }
+
+    /// Continues writing after the records in `file`.
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub(crate) fn append(kind: WriterKind, file: File, preallocate: u64) -> io::Result<Self> {
+        match kind {
+            WriterKind::Buffered => Ok(Self::buffered(file)),
+            #[cfg(unix)]
+            WriterKind::Mmap => MmapWriter::append(file, preallocate).map(LogFileWriter::Mmap),
+            #[cfg(not(unix))]
+            WriterKind::Mmap => Err(Self::unsupported()),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            WriterKind::IoUring => Self::uring(file),
+        }
+    }
+
+    fn buffered(file: File) -> Self {
+        LogFileWriter::Buffered(BufWriter::with_capacity(BUFFER_CAPACITY, file))
+    }
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn uring(file: File) -> io::Result<Self> {
+        static WARN: std::sync::Once = std::sync::Once::new();
+        match UringWriter::new(file.try_clone()?, BUFFER_CAPACITY) {
+            Ok(writer) => Ok(LogFileWriter::IoUring(Box::new(writer))),
+            Err(e) => {
+                WARN.call_once(|| {
+                    eprintln!(
+                        "WARNING: io_uring is not available, writing buffered  {}",
+                        e
+                    )
+                });
+                Ok(Self::buffered(file))
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the mmap writer is only available on unix",
+        )
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        match self {
+            LogFileWriter::Buffered(writer) => writer.get_ref(),
+            #[cfg(unix)]
+            LogFileWriter::Mmap(writer) => writer.file(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            LogFileWriter::IoUring(writer) => writer.file(),
+        }
+    }
+
+    /// Length of the records in the file, including those not flushed yet
+    pub(crate) fn len(&self) -> u64 {
+        match self {
+            LogFileWriter::Buffered(writer) => {
+                let file_len = writer.get_ref().metadata().map_or(0, |m| m.len());
+                file_len + writer.buffer().len() as u64
+            }
+            #[cfg(unix)]
+            LogFileWriter::Mmap(writer) => writer.len(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            LogFileWriter::IoUring(writer) => writer.len(),
+        }
+    }
+
+    /// Starts writing the buffered records to the file. Only the io_uring writer returns before
+    /// they are written, `flush` waits for them.
+    pub(crate) fn submit(&mut self) -> io::Result<()> {
+        match self {
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            LogFileWriter::IoUring(writer) => writer.submit(),
+            _ => self.flush(),
+        }
+    }
+
+    /// Syncs what was flushed so far to disk.
+    pub(crate) fn sync_data(&mut self) -> io::Result<()> {
+        match self {
+            LogFileWriter::Buffered(writer) => writer.get_ref().sync_data(),
+            #[cfg(unix)]
+            LogFileWriter::Mmap(writer) => writer.sync_data(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            LogFileWriter::IoUring(writer) => writer.sync_data(),
+        }
+    }
+
+    /// Flushes and closes the writer, returning the file.
+    pub(crate) fn into_file(self) -> io::Result<File> {
+        match self {
+            LogFileWriter::Buffered(writer) => writer.into_inner().map_err(|e| e.into_error()),
+            #[cfg(unix)]
+            LogFileWriter::Mmap(writer) => writer.finish(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            LogFileWriter::IoUring(writer) => (*writer).finish(),
+        }
+    }
+
+    /// Closes the writer without flushing, returning the records it did not write to the file
+    /// yet.
+    pub(crate) fn into_unwritten(self) -> Option<Vec<u8>> {
+        match self {
+            LogFileWriter::Buffered(writer) => writer.into_parts().1.ok(),
+            #[cfg(unix)]
+            LogFileWriter::Mmap(_) => None,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            LogFileWriter::IoUring(writer) => Some((*writer).into_unwritten()),
+        }
+    }
+}
+
+impl Write for LogFileWriter {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            LogFileWriter::Buffered(writer) => writer.write(buf),
+            #[cfg(unix)]
+            LogFileWriter::Mmap(writer) => writer.write(buf),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            LogFileWriter::IoUring(writer) => writer.write(buf),
+        }
+    }
+
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            LogFileWriter::Buffered(writer) => writer.write_all(buf),
+            #[cfg(unix)]
+            LogFileWriter::Mmap(writer) => writer.write_all(buf),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            LogFileWriter::IoUring(writer) => writer.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LogFileWriter::Buffered(writer) => writer.flush(),
+            #[cfg(unix)]
+            LogFileWriter::Mmap(writer) => writer.flush(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            LogFileWriter::IoUring(writer) => writer.flush(),
+        }
+    }
+}